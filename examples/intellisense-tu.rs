@@ -23,6 +23,28 @@ fn main() {
         .parse_translation_unit(name, &args, &[&unsaved_file], local_options)
         .unwrap();
 
+    let display_options = intellisense
+        .get_default_diagnostic_display_options()
+        .unwrap();
+    for diagnostic in translation_unit.diagnostics().unwrap() {
+        println!(
+            "Diagnostic {:?}: {}",
+            diagnostic.get_severity().unwrap(),
+            diagnostic.format(display_options).unwrap()
+        );
+        for fix_it in diagnostic.get_fix_its().unwrap() {
+            println!("  Fix-it {:?}", fix_it.text);
+        }
+    }
+
+    for inclusion in translation_unit.inclusions().unwrap() {
+        println!(
+            "Inclusion {:?} at depth {}",
+            inclusion.file.get_name().unwrap(),
+            inclusion.stack.len()
+        );
+    }
+
     let cursor = translation_unit.get_cursor().unwrap();
 
     {
@@ -41,6 +63,13 @@ fn main() {
 
         let cursor_kind_flags = cursor.get_kind_flags().unwrap();
         println!("CursorKindFlags {:?}", cursor_kind_flags);
+
+        for token in translation_unit.tokenize(&range).unwrap() {
+            println!(
+                "Token {:?} {:?} at {}..{}",
+                token.kind, token.spelling, token.start_offset, token.end_offset
+            );
+        }
     }
 
     let child_cursors = cursor.get_all_children().unwrap();
@@ -0,0 +1,43 @@
+use hassle_rs::*;
+
+fn main() {
+    let source = include_str!("copy.hlsl");
+
+    let dxc = Dxc::new(None).unwrap();
+    let compiler = dxc.create_compiler().unwrap();
+    let library = dxc.create_library().unwrap();
+
+    let blob = library.create_blob_with_encoding_from_str(source).unwrap();
+
+    let (result, (pdb_name, pdb)) = compiler
+        .compile_with_debug(
+            &blob,
+            "copy.hlsl",
+            "copyCs",
+            "cs_6_0",
+            &["-Zi", "-Qembed_debug"],
+            None,
+            &[("MY_DEFINE", Some("1"))],
+        )
+        .unwrap();
+    OperationOutput::from_operation_result(result).unwrap();
+
+    let pdb_utils = dxc.create_pdb_utils().unwrap();
+    pdb_utils.load(&pdb).unwrap();
+
+    println!("PDB {:?}", pdb_name);
+    println!("Main file: {}", pdb_utils.get_main_file_name().unwrap());
+    println!("Entry point: {}", pdb_utils.get_entry_point().unwrap());
+    println!(
+        "Target profile: {}",
+        pdb_utils.get_target_profile().unwrap()
+    );
+    println!("Arguments: {:?}", pdb_utils.get_args().unwrap());
+    println!("Defines: {:?}", pdb_utils.get_defines().unwrap());
+    println!("Hash: {:x?}", pdb_utils.get_hash().unwrap().as_slice());
+
+    for source in pdb_utils.get_sources().unwrap() {
+        println!("---- {} ----", source.name);
+        println!("{}", source.content.as_str().unwrap());
+    }
+}
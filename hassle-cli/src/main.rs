@@ -0,0 +1,639 @@
+use clap::{Args, Parser, Subcommand};
+use hassle_rs::intellisense::*;
+use hassle_rs::*;
+use serde_json::json;
+use std::convert::TryInto;
+use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+type CliResult<T = ()> = Result<T, Box<dyn Error>>;
+
+/// Compile, validate, sign and inspect HLSL shaders with DXC.
+#[derive(Parser)]
+#[command(name = "hassle", version)]
+struct Cli {
+    /// Path to `libdxcompiler.so`/`dxcompiler.dll`, instead of searching the default library paths.
+    #[arg(long, global = true, value_name = "PATH")]
+    dxc: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile HLSL to DXIL, or to SPIR-V with `--spirv`.
+    Compile(CompileArgs),
+    /// Run only the preprocessor.
+    Preprocess(PreprocessArgs),
+    /// Disassemble a DXIL container or SPIR-V module.
+    Disassemble(BinaryArgs),
+    /// Validate and sign a DXIL container with `dxil.dll`/`libdxil.so`.
+    Validate(ValidateArgs),
+    /// Sign a DXIL container without validating it.
+    FakeSign(BinaryArgs),
+    /// List the parts of a DXIL container.
+    Container(InputArgs),
+    /// Print the reflection data of a DXIL container as JSON.
+    Reflect(InputArgs),
+    /// Print the abstract syntax tree of an HLSL file.
+    Ast(AstArgs),
+}
+
+#[derive(Args)]
+struct SourceArgs {
+    /// HLSL source file.
+    input: PathBuf,
+
+    /// Define a macro, as `NAME` or `NAME=VALUE`.
+    #[arg(short = 'D', value_name = "DEFINE")]
+    defines: Vec<String>,
+
+    /// Add a directory to the include search path.
+    #[arg(short = 'I', value_name = "DIR")]
+    include_dirs: Vec<PathBuf>,
+}
+
+impl SourceArgs {
+    fn read(&self) -> CliResult<String> {
+        std::fs::read_to_string(&self.input)
+            .map_err(|e| format!("Failed to read {:?}: {}", self.input, e).into())
+    }
+
+    fn source_name(&self) -> String {
+        self.input.to_string_lossy().into_owned()
+    }
+
+    fn defines(&self) -> Vec<(&str, Option<&str>)> {
+        self.defines
+            .iter()
+            .map(|define| match define.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (define.as_str(), None),
+            })
+            .collect()
+    }
+
+    fn include_handler(&self) -> FileSystemIncludeHandler {
+        // DXC resolves includes relative to `input` as given, which is relative to the working
+        // directory or absolute
+        FileSystemIncludeHandler::new(".").with_search_dirs(self.include_dirs.iter().cloned())
+    }
+}
+
+#[derive(Args)]
+struct CompileArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Entry point name.
+    #[arg(short = 'E', long = "entry", default_value = "main")]
+    entry_point: String,
+
+    /// Target profile, such as `cs_6_5`.
+    #[arg(short = 'T', long = "profile")]
+    target_profile: String,
+
+    /// Generate SPIR-V instead of DXIL.
+    #[arg(long)]
+    spirv: bool,
+
+    /// Output file, defaults to standard output.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Additional arguments passed verbatim to DXC.
+    #[arg(last = true)]
+    args: Vec<String>,
+}
+
+#[derive(Args)]
+struct PreprocessArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Output file, defaults to standard output.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// Compiled shader.
+    input: PathBuf,
+}
+
+#[derive(Args)]
+struct BinaryArgs {
+    /// Compiled shader.
+    input: PathBuf,
+
+    /// Output file, defaults to standard output for `disassemble` and to overwriting the input
+    /// when signing.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    #[command(flatten)]
+    binary: BinaryArgs,
+
+    /// Path to `libdxil.so`/`dxil.dll`, instead of searching the default library paths.
+    #[arg(long, value_name = "PATH")]
+    dxil: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct AstArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Additional arguments passed verbatim to the parser.
+    #[arg(last = true)]
+    args: Vec<String>,
+}
+
+fn read_binary(path: &Path) -> CliResult<Vec<u8>> {
+    std::fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e).into())
+}
+
+fn write_output(output: Option<&Path>, data: &[u8]) -> CliResult {
+    match output {
+        Some(path) => std::fs::write(path, data)
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e).into()),
+        None => Ok(std::io::stdout().lock().write_all(data)?),
+    }
+}
+
+fn diagnostic_renderer<'a>() -> DiagnosticRenderer<'a> {
+    let colors = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    DiagnosticRenderer::new().with_colors(colors)
+}
+
+/// Prints the messages of `result` as rendered diagnostics, falling back to the raw text if they
+/// cannot be parsed, and returns the output blob.
+fn finish(
+    result: DxcOperationResult,
+    mut renderer: DiagnosticRenderer<'_>,
+    what: &str,
+) -> CliResult<Vec<u8>> {
+    let mut report = |messages: &str| {
+        let diagnostics = parse_diagnostics(messages);
+        if diagnostics.is_empty() {
+            eprint!("{}", messages);
+        } else {
+            eprint!("{}", renderer.render_all(&diagnostics));
+        }
+    };
+
+    match OperationOutput::from_operation_result(result) {
+        Ok(output) => {
+            if let Some(messages) = &output.messages {
+                report(messages);
+            }
+            Ok(output.blob)
+        }
+        Err(HassleError::OperationError(_, messages)) if !messages.is_empty() => {
+            report(&messages);
+            Err(format!("{} failed", what).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn compile(dxc: &Dxc, args: &CompileArgs) -> CliResult {
+    let compiler = dxc.create_compiler()?;
+    let library = dxc.create_library()?;
+
+    let source = args.source.read()?;
+    let blob = library.create_blob_with_encoding_from_str(&source)?;
+
+    let mut dxc_args = args.args.iter().map(String::as_str).collect::<Vec<_>>();
+    if args.spirv {
+        dxc_args.push("-spirv");
+    }
+
+    let mut include_handler = args.source.include_handler();
+    let result = compiler.compile(
+        &blob,
+        &args.source.source_name(),
+        &args.entry_point,
+        &args.target_profile,
+        &dxc_args,
+        Some(&mut include_handler),
+        &args.source.defines(),
+    )?;
+
+    let renderer = diagnostic_renderer()
+        .with_source(&args.source.source_name(), source)
+        .with_include_handler(&include_handler);
+    let blob = finish(result, renderer, "Compilation")?;
+    write_output(args.output.as_deref(), &blob)
+}
+
+fn preprocess(dxc: &Dxc, args: &PreprocessArgs) -> CliResult {
+    let compiler = dxc.create_compiler()?;
+    let library = dxc.create_library()?;
+
+    let source = args.source.read()?;
+    let blob = library.create_blob_with_encoding_from_str(&source)?;
+
+    let mut include_handler = args.source.include_handler();
+    let result = compiler.preprocess(
+        &blob,
+        &args.source.source_name(),
+        &[],
+        Some(&mut include_handler),
+        &args.source.defines(),
+    )?;
+
+    let renderer = diagnostic_renderer()
+        .with_source(&args.source.source_name(), source)
+        .with_include_handler(&include_handler);
+    let blob = finish(result, renderer, "Preprocessing")?;
+    write_output(args.output.as_deref(), &blob)
+}
+
+fn disassemble(dxc: &Dxc, args: &BinaryArgs) -> CliResult {
+    let compiler = dxc.create_compiler()?;
+    let library = dxc.create_library()?;
+
+    let data = read_binary(&args.input)?;
+    let blob = library.create_blob_with_encoding(&data)?;
+    let disassembly = compiler.disassemble(&blob.into())?;
+
+    write_output(args.output.as_deref(), disassembly.as_slice())
+}
+
+fn validate(dxc: &Dxc, args: &ValidateArgs) -> CliResult {
+    let dxil = match &args.dxil {
+        Some(path) => Dxil::new(Some(path.clone()))?,
+        None => Dxil::new_next_to(dxc)?,
+    };
+    let validator = dxil.create_validator()?;
+    let library = dxc.create_library()?;
+
+    let data = read_binary(&args.binary.input)?;
+    let blob = library.create_blob_with_encoding(&data)?;
+
+    let blob = finish(
+        validator.validate(&blob)?,
+        diagnostic_renderer(),
+        "Validation",
+    )?;
+
+    let output_path = args.binary.output.as_ref().unwrap_or(&args.binary.input);
+    write_output(Some(output_path), &blob)
+}
+
+fn fake_sign(args: &BinaryArgs) -> CliResult {
+    let mut data = read_binary(&args.input)?;
+    if !fake_sign_dxil_in_place(&mut data) {
+        return Err(format!("{:?} is not a well-formed DXIL container", args.input).into());
+    }
+
+    let output_path = args.output.as_ref().unwrap_or(&args.input);
+    write_output(Some(output_path), &data)
+}
+
+/// A part of a `DXBC` container, see
+/// <https://github.com/microsoft/DirectXShaderCompiler/blob/main/include/dxc/DxilContainer/DxilContainer.h>.
+struct ContainerPart<'a> {
+    fourcc: [u8; 4],
+    data: &'a [u8],
+}
+
+struct Container<'a> {
+    hash: [u8; 16],
+    version: (u16, u16),
+    parts: Vec<ContainerPart<'a>>,
+}
+
+fn parse_container(data: &[u8]) -> Option<Container<'_>> {
+    // Offsets come from the (untrusted) input, so never let them overflow
+    let u32_at = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            data.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+        ))
+    };
+
+    if data.get(0..4)? != b"DXBC" || u32_at(24)? as usize != data.len() {
+        return None;
+    }
+
+    let hash = data.get(4..20)?.try_into().ok()?;
+    let version = (
+        u16::from_le_bytes(data.get(20..22)?.try_into().ok()?),
+        u16::from_le_bytes(data.get(22..24)?.try_into().ok()?),
+    );
+
+    let num_parts = u32_at(28)? as usize;
+    let parts = (0..num_parts)
+        .map(|index| {
+            let offset = u32_at(index.checked_mul(4)?.checked_add(32)?)? as usize;
+            let fourcc = data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+            let size = u32_at(offset.checked_add(4)?)? as usize;
+            let start = offset.checked_add(8)?;
+            let data = data.get(start..start.checked_add(size)?)?;
+            Some(ContainerPart { fourcc, data })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(Container {
+        hash,
+        version,
+        parts,
+    })
+}
+
+fn container(args: &InputArgs) -> CliResult {
+    let data = read_binary(&args.input)?;
+    let container = parse_container(&data)
+        .ok_or_else(|| format!("{:?} is not a well-formed DXIL container", args.input))?;
+
+    let hash = container
+        .hash
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    println!("hash:    {}", hash);
+    println!("version: {}.{}", container.version.0, container.version.1);
+    println!("size:    {} bytes", data.len());
+    println!("parts:   {}", container.parts.len());
+    for (index, part) in container.parts.iter().enumerate() {
+        println!(
+            "  {:>2}: {} {:>8} bytes",
+            index,
+            String::from_utf8_lossy(&part.fourcc),
+            part.data.len()
+        );
+    }
+
+    Ok(())
+}
+
+fn shader_input_type_name(input_type: u32) -> Option<&'static str> {
+    // D3D_SHADER_INPUT_TYPE
+    Some(match input_type {
+        0 => "cbuffer",
+        1 => "tbuffer",
+        2 => "texture",
+        3 => "sampler",
+        4 => "uav_rw_typed",
+        5 => "structured",
+        6 => "uav_rw_structured",
+        7 => "byte_address",
+        8 => "uav_rw_byte_address",
+        9 => "uav_append_structured",
+        10 => "uav_consume_structured",
+        11 => "uav_rw_structured_with_counter",
+        12 => "rt_acceleration_structure",
+        13 => "uav_feedback_texture",
+        _ => return None,
+    })
+}
+
+fn reflect(dxc: &Dxc, args: &InputArgs) -> CliResult {
+    let library = dxc.create_library()?;
+    let reflector = dxc.create_reflector()?;
+
+    let data = read_binary(&args.input)?;
+    let blob = library.create_blob_with_encoding(&data)?;
+    let reflection = reflector.reflect(blob.into())?;
+
+    let signature = |parameters: Vec<SignatureParameter>| {
+        parameters
+            .into_iter()
+            .map(|p| {
+                json!({
+                    "semantic_name": p.semantic_name,
+                    "semantic_index": p.semantic_index,
+                    "register": p.register,
+                    "system_value_type": p.system_value_type,
+                    "component_type": p.component_type,
+                    "mask": p.mask,
+                    "read_write_mask": p.read_write_mask,
+                    "stream": p.stream,
+                    "min_precision": p.min_precision,
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let resources = reflection
+        .bound_resources()?
+        .into_iter()
+        .map(|r| {
+            json!({
+                "name": r.name,
+                "type": shader_input_type_name(r.input_type),
+                "input_type": r.input_type,
+                "bind_point": r.bind_point,
+                "bind_count": r.bind_count,
+                "space": r.space,
+                "flags": r.flags,
+                "return_type": r.return_type,
+                "dimension": r.dimension,
+                "num_samples": r.num_samples,
+                "id": r.id,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let json = json!({
+        "thread_group_size": reflection.thread_group_size(),
+        "requires_flags": reflection.requires_flags(),
+        "bound_resources": resources,
+        "input_parameters": signature(reflection.input_parameters()?),
+        "output_parameters": signature(reflection.output_parameters()?),
+    });
+
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(())
+}
+
+fn print_cursor_tree(cursor: &DxcCursor, indent: usize) -> CliResult {
+    let DxcSourceOffsets {
+        start_offset,
+        end_offset,
+    } = cursor.get_extent()?.get_offsets()?;
+
+    println!(
+        "{: <indent$}{:?} {:?} [{}..{}] type: {:?}",
+        "",
+        cursor.get_kind()?,
+        cursor.get_display_name()?,
+        start_offset,
+        end_offset,
+        cursor.get_cursor_type()?.get_spelling()?,
+        indent = indent * 2,
+    );
+
+    for child in cursor.get_all_children()? {
+        print_cursor_tree(&child, indent + 1)?;
+    }
+    Ok(())
+}
+
+fn ast(dxc: &Dxc, args: &AstArgs) -> CliResult {
+    let source = args.source.read()?;
+    let source_name = args.source.source_name();
+
+    let mut parse_args = args
+        .source
+        .defines
+        .iter()
+        .map(|define| format!("-D{}", define))
+        .collect::<Vec<_>>();
+    parse_args.extend(
+        args.source
+            .include_dirs
+            .iter()
+            .map(|dir| format!("-I{}", dir.display())),
+    );
+    parse_args.extend(args.args.iter().cloned());
+    let parse_args = parse_args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let intellisense = dxc.create_intellisense()?;
+    let options = intellisense.get_default_editing_tu_options()?;
+    let index = intellisense.create_index()?;
+    let unsaved_file = intellisense.create_unsaved_file(&source_name, &source)?;
+    let translation_unit =
+        index.parse_translation_unit(&source_name, &parse_args, &[&unsaved_file], options)?;
+
+    print_cursor_tree(&translation_unit.get_cursor()?, 0)
+}
+
+fn run(cli: Cli) -> CliResult {
+    // Only load DXC for the commands that need it, signing and container inspection work without
+    let load_dxc = || Dxc::new(cli.dxc.clone());
+
+    match &cli.command {
+        Command::Compile(args) => compile(&load_dxc()?, args),
+        Command::Preprocess(args) => preprocess(&load_dxc()?, args),
+        Command::Disassemble(args) => disassemble(&load_dxc()?, args),
+        Command::Validate(args) => validate(&load_dxc()?, args),
+        Command::FakeSign(args) => fake_sign(args),
+        Command::Container(args) => container(args),
+        Command::Reflect(args) => reflect(&load_dxc()?, args),
+        Command::Ast(args) => ast(&load_dxc()?, args),
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a container with a zero hash, version 1.0 and the given parts.
+    fn build_container(parts: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let header_size = 32 + parts.len() * 4;
+        let mut offsets = vec![];
+        let mut body = vec![];
+        for (fourcc, data) in parts {
+            offsets.push((header_size + body.len()) as u32);
+            body.extend_from_slice(*fourcc);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+        }
+
+        let mut container = b"DXBC".to_vec();
+        container.extend_from_slice(&[0; 16]);
+        container.extend_from_slice(&1u16.to_le_bytes());
+        container.extend_from_slice(&0u16.to_le_bytes());
+        container.extend_from_slice(&((header_size + body.len()) as u32).to_le_bytes());
+        container.extend_from_slice(&(parts.len() as u32).to_le_bytes());
+        for offset in offsets {
+            container.extend_from_slice(&offset.to_le_bytes());
+        }
+        container.extend_from_slice(&body);
+        container
+    }
+
+    fn set_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn parses_valid_container() {
+        let data = build_container(&[(b"DXIL", &[1, 2, 3]), (b"HASH", &[])]);
+        let container = parse_container(&data).unwrap();
+
+        assert_eq!(container.hash, [0; 16]);
+        assert_eq!(container.version, (1, 0));
+        assert_eq!(container.parts.len(), 2);
+        assert_eq!(&container.parts[0].fourcc, b"DXIL");
+        assert_eq!(container.parts[0].data, &[1, 2, 3]);
+        assert_eq!(&container.parts[1].fourcc, b"HASH");
+        assert!(container.parts[1].data.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let data = build_container(&[]);
+        assert_eq!(data.len(), 32);
+        assert!(parse_container(&data).is_some());
+
+        for len in 0..data.len() {
+            let mut truncated = data[..len].to_vec();
+            if len >= 28 {
+                // Keep the declared size consistent, so only the missing part count is at fault
+                set_u32(&mut truncated, 24, len as u32);
+            }
+            assert!(parse_container(&truncated).is_none(), "length {}", len);
+        }
+
+        let mut wrong_magic = data.clone();
+        wrong_magic[0] = b'X';
+        assert!(parse_container(&wrong_magic).is_none());
+
+        let mut wrong_size = data;
+        set_u32(&mut wrong_size, 24, 31);
+        assert!(parse_container(&wrong_size).is_none());
+    }
+
+    #[test]
+    fn rejects_out_of_range_parts() {
+        let data = build_container(&[(b"DXIL", &[1, 2, 3])]);
+        let len = data.len() as u32;
+
+        for offset in [len, len - 4, len - 8, u32::MAX - 3, u32::MAX] {
+            let mut bad_offset = data.clone();
+            set_u32(&mut bad_offset, 32, offset);
+            assert!(parse_container(&bad_offset).is_none(), "offset {}", offset);
+        }
+
+        for size in [4, u32::MAX - 7, u32::MAX] {
+            let mut bad_size = data.clone();
+            set_u32(&mut bad_size, 36 + 4, size);
+            assert!(parse_container(&bad_size).is_none(), "size {}", size);
+        }
+    }
+
+    #[test]
+    fn rejects_part_count_overflow() {
+        let data = build_container(&[(b"DXIL", &[1, 2, 3])]);
+
+        for num_parts in [2, 0x4000_0000, u32::MAX] {
+            let mut bad_count = data.clone();
+            set_u32(&mut bad_count, 28, num_parts);
+            assert!(
+                parse_container(&bad_count).is_none(),
+                "part count {}",
+                num_parts
+            );
+        }
+    }
+}
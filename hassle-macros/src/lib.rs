@@ -0,0 +1,335 @@
+//! Procedural macros that compile HLSL at Rust compile time through [`hassle_rs`].
+
+use hassle_rs::{CompileJob, Dxc, FileSystemIncludeHandler};
+use proc_macro2::{Literal, Span, TokenStream};
+use quote::quote;
+use std::path::{Path, PathBuf};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{bracketed, parse_macro_input, Ident, LitStr, Token};
+
+struct IncludeHlsl {
+    path: LitStr,
+    entry_point: Option<LitStr>,
+    target_profile: Option<LitStr>,
+    defines: Vec<LitStr>,
+    args: Vec<LitStr>,
+    include_dirs: Vec<LitStr>,
+}
+
+impl Parse for IncludeHlsl {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let mut result = Self {
+            path: input.parse()?,
+            entry_point: None,
+            target_profile: None,
+            defines: vec![],
+            args: vec![],
+            include_dirs: vec![],
+        };
+
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            let key = input.parse::<Ident>()?;
+            input.parse::<Token![=]>()?;
+
+            let parse_list = |input: ParseStream<'_>| -> syn::Result<Vec<LitStr>> {
+                let content;
+                bracketed!(content in input);
+                Ok(Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?
+                    .into_iter()
+                    .collect())
+            };
+
+            match key.to_string().as_str() {
+                "entry" => result.entry_point = Some(input.parse()?),
+                "profile" => result.target_profile = Some(input.parse()?),
+                "defines" => result.defines = parse_list(input)?,
+                "args" => result.args = parse_list(input)?,
+                "include_dirs" => result.include_dirs = parse_list(input)?,
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        "expected one of `entry`, `profile`, `defines`, `args` or `include_dirs`",
+                    ))
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+fn manifest_dir() -> PathBuf {
+    std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+}
+
+/// Reads `path` through `include_bytes!()`, so that rustc recompiles the calling crate when it
+/// changes.
+fn track_file(path: &Path) -> TokenStream {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let path = path.to_string_lossy();
+    quote!(
+        const _: &[u8] = ::core::include_bytes!(#path);
+    )
+}
+
+/// Splits a `NAME` or `NAME=VALUE` define.
+fn parse_define(define: &str) -> (String, Option<String>) {
+    match define.split_once('=') {
+        Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+        None => (define.to_owned(), None),
+    }
+}
+
+/// Reinterprets little-endian `bytes` as 32-bit words, or returns [`None`] if the length is not
+/// a multiple of 4.
+fn to_words(bytes: &[u8]) -> Option<Vec<u32>> {
+    let chunks = bytes.chunks_exact(4);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    Some(
+        chunks
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect(),
+    )
+}
+
+/// The type of slice the compiled shader is emitted as.
+#[derive(Clone, Copy)]
+enum Output {
+    Bytes,
+    Words,
+}
+
+fn expand(input: IncludeHlsl, output_type: Output) -> syn::Result<TokenStream> {
+    let target_profile = input.target_profile.as_ref().ok_or_else(|| {
+        syn::Error::new(Span::call_site(), "missing `profile = \"...\"` argument")
+    })?;
+
+    let path = manifest_dir().join(input.path.value());
+    let source = std::fs::read_to_string(&path).map_err(|e| {
+        syn::Error::new(
+            input.path.span(),
+            format!("failed to read {:?}: {}", path, e),
+        )
+    })?;
+
+    let job = CompileJob {
+        source_name: path.to_string_lossy().into_owned(),
+        source,
+        entry_point: input
+            .entry_point
+            .as_ref()
+            .map_or_else(|| "main".to_owned(), LitStr::value),
+        target_profile: target_profile.value(),
+        args: input.args.iter().map(LitStr::value).collect(),
+        defines: input
+            .defines
+            .iter()
+            .map(|define| parse_define(&define.value()))
+            .collect(),
+    };
+
+    let dxc_error =
+        |e: hassle_rs::HassleError| syn::Error::new(Span::call_site(), format!("{}", e));
+    let dxc = Dxc::new(None).map_err(dxc_error)?;
+    let compiler = dxc.create_compiler().map_err(dxc_error)?;
+    let library = dxc.create_library().map_err(dxc_error)?;
+
+    // The source name is absolute, so DXC requests includes by absolute path too
+    let mut include_handler = FileSystemIncludeHandler::new(manifest_dir()).with_search_dirs(
+        input
+            .include_dirs
+            .iter()
+            .map(|dir| manifest_dir().join(dir.value())),
+    );
+    let output = job
+        .compile(&compiler, &library, Some(&mut include_handler))
+        .map_err(|e| {
+            syn::Error::new(
+                Span::call_site(),
+                format!("failed to compile {:?}:\n{}", path, e),
+            )
+        })?;
+
+    let tracked_files = std::iter::once(path.as_path())
+        .chain(
+            include_handler
+                .resolved_includes()
+                .iter()
+                .map(|include| include.path.as_path()),
+        )
+        .map(track_file);
+    let value = match output_type {
+        Output::Bytes => {
+            let bytes = Literal::byte_string(&output.blob);
+            quote!(#bytes as &'static [u8])
+        }
+        Output::Words => {
+            let words = to_words(&output.blob).ok_or_else(|| {
+                syn::Error::new(
+                    Span::call_site(),
+                    format!(
+                        "output of {:?} is {} bytes, which is not a whole number of 32-bit words",
+                        path,
+                        output.blob.len()
+                    ),
+                )
+            })?;
+            let words = words.into_iter().map(Literal::u32_suffixed);
+            quote!(&[#(#words),*] as &'static [u32])
+        }
+    };
+
+    Ok(quote! {
+        {
+            #(#tracked_files)*
+            #value
+        }
+    })
+}
+
+/// Compiles an HLSL file and evaluates to the resulting DXIL or SPIR-V as a `&'static [u8]`.
+///
+/// ```ignore
+/// const COPY_CS: &[u8] = hassle_macros::include_hlsl!(
+///     "shaders/copy.hlsl",
+///     entry = "copyCs",
+///     profile = "cs_6_5",
+///     defines = ["USE_FEATURE", "GROUP_SIZE=8"],
+///     args = ["-spirv"],
+///     include_dirs = ["shaders/include"],
+/// );
+/// ```
+///
+/// The path and `include_dirs` are relative to the directory containing the calling crate's
+/// `Cargo.toml`.  `entry` defaults to `main`, `profile` is required, and `defines` take the form
+/// `NAME` or `NAME=VALUE`.
+///
+/// Compilation errors are reported as Rust compile errors at the macro invocation.  Warnings are
+/// not reported, as procedural macros cannot emit them on stable Rust.  The calling crate is
+/// recompiled whenever the shader or any of its includes change.
+///
+/// DXC is searched for as described by [`hassle_rs::Dxc::new()`], so `HASSLE_DXC_PATH` can point
+/// it at a specific library.
+///
+/// The bytes are only guaranteed to be 1-byte aligned, so they cannot be reinterpreted as a
+/// `&[u32]` in place.  Use [`include_hlsl_words!`] for SPIR-V that is consumed as 32-bit words.
+#[proc_macro]
+pub fn include_hlsl(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as IncludeHlsl);
+    expand(input, Output::Bytes)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Like [`include_hlsl!`], but evaluates to a `&'static [u32]` of little-endian words, as
+/// expected by APIs that consume SPIR-V.
+///
+/// ```ignore
+/// const COPY_CS: &[u32] = hassle_macros::include_hlsl_words!(
+///     "shaders/copy.hlsl",
+///     entry = "copyCs",
+///     profile = "cs_6_5",
+///     args = ["-spirv"],
+/// );
+/// ```
+///
+/// Fails to compile if the output is not a whole number of words.
+#[proc_macro]
+pub fn include_hlsl_words(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as IncludeHlsl);
+    expand(input, Output::Words)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(list: &[LitStr]) -> Vec<String> {
+        list.iter().map(LitStr::value).collect()
+    }
+
+    #[test]
+    fn parses_path_only() {
+        let input = syn::parse_str::<IncludeHlsl>(r#""shaders/copy.hlsl""#).unwrap();
+        assert_eq!(input.path.value(), "shaders/copy.hlsl");
+        assert!(input.entry_point.is_none());
+        assert!(input.target_profile.is_none());
+        assert!(input.defines.is_empty());
+        assert!(input.args.is_empty());
+        assert!(input.include_dirs.is_empty());
+
+        assert!(syn::parse_str::<IncludeHlsl>(r#""shaders/copy.hlsl","#).is_ok());
+    }
+
+    #[test]
+    fn parses_all_arguments() {
+        let input = syn::parse_str::<IncludeHlsl>(
+            r#""shaders/copy.hlsl",
+            include_dirs = ["shaders/include"],
+            profile = "cs_6_5",
+            entry = "copyCs",
+            defines = ["USE_FEATURE", "GROUP_SIZE=8",],
+            args = [],"#,
+        )
+        .unwrap();
+        assert_eq!(input.entry_point.unwrap().value(), "copyCs");
+        assert_eq!(input.target_profile.unwrap().value(), "cs_6_5");
+        assert_eq!(values(&input.defines), ["USE_FEATURE", "GROUP_SIZE=8"]);
+        assert!(input.args.is_empty());
+        assert_eq!(values(&input.include_dirs), ["shaders/include"]);
+    }
+
+    #[test]
+    fn rejects_malformed_arguments() {
+        for input in [
+            "",
+            "shaders",
+            r#""copy.hlsl" profile = "cs_6_5""#,
+            r#""copy.hlsl", target = "cs_6_5""#,
+            r#""copy.hlsl", profile"#,
+            r#""copy.hlsl", profile = cs_6_5"#,
+            r#""copy.hlsl", defines = "A""#,
+            r#""copy.hlsl", args = [-spirv]"#,
+        ] {
+            assert!(syn::parse_str::<IncludeHlsl>(input).is_err(), "{}", input);
+        }
+
+        let error = syn::parse_str::<IncludeHlsl>(r#""copy.hlsl", target = "cs_6_5""#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().starts_with("expected one of `entry`"));
+    }
+
+    #[test]
+    fn splits_defines() {
+        assert_eq!(parse_define("A"), ("A".to_owned(), None));
+        assert_eq!(parse_define("A=1"), ("A".to_owned(), Some("1".to_owned())));
+        assert_eq!(parse_define("A="), ("A".to_owned(), Some(String::new())));
+        assert_eq!(
+            parse_define("A=B=C"),
+            ("A".to_owned(), Some("B=C".to_owned()))
+        );
+    }
+
+    #[test]
+    fn converts_words() {
+        assert_eq!(
+            to_words(&[0x03, 0x02, 0x23, 0x07, 1, 0, 0, 0]),
+            Some(vec![0x0723_0203, 1])
+        );
+        assert_eq!(to_words(&[]), Some(vec![]));
+        assert_eq!(to_words(&[0x03, 0x02, 0x23]), None);
+    }
+}
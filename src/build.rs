@@ -0,0 +1,295 @@
+//! Helpers for compiling shaders from a `build.rs` script.
+//!
+//! ```no_run
+//! // build.rs
+//! hassle_rs::build::Build::new()
+//!     .with_include_dir("shaders/include")
+//!     .with_shader(hassle_rs::build::Shader::new("shaders/copy.hlsl", "copyCs", "cs_6_5"))
+//!     .with_shader(
+//!         hassle_rs::build::Shader::new("shaders/copy.hlsl", "copyCs", "cs_6_5")
+//!             .with_arg("-spirv")
+//!             .with_output_name("copy.spv"),
+//!     )
+//!     .compile();
+//! ```
+//!
+//! The outputs can then be embedded with
+//! `include_bytes!(concat!(env!("OUT_DIR"), "/copy.copyCs.dxil"))`.
+
+use crate::include::FileSystemIncludeHandler;
+use crate::pool::CompileJob;
+use crate::utils::HassleError;
+use crate::wrapper::Dxc;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BuildError {
+    #[error("OUT_DIR is not set, specify the output directory with `Build::with_out_dir()`")]
+    MissingOutDir,
+    #[error("Failed to load DXC: {0}")]
+    LoadDxc(#[source] HassleError),
+    #[error("Failed to read {path:?}: {inner}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        inner: std::io::Error,
+    },
+    #[error("Failed to compile {path:?} (entry point `{entry_point}`, profile `{target_profile}`):\n{inner}")]
+    Compile {
+        path: PathBuf,
+        entry_point: String,
+        target_profile: String,
+        #[source]
+        inner: Box<HassleError>,
+    },
+    #[error("Failed to write {path:?}: {inner}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        inner: std::io::Error,
+    },
+}
+
+/// A shader to compile with [`Build`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Shader {
+    pub path: PathBuf,
+    pub entry_point: String,
+    pub target_profile: String,
+    pub args: Vec<String>,
+    pub defines: Vec<(String, Option<String>)>,
+    pub output_name: Option<String>,
+}
+
+impl Shader {
+    /// `path` is relative to the working directory of the build script, which is the directory
+    /// containing the package's `Cargo.toml`.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        entry_point: impl Into<String>,
+        target_profile: impl Into<String>,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            entry_point: entry_point.into(),
+            target_profile: target_profile.into(),
+            args: vec![],
+            defines: vec![],
+            output_name: None,
+        }
+    }
+
+    pub fn with_arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Adds a define, where a value of [`None`] defines `name` as `1`.
+    pub fn with_define(mut self, name: impl Into<String>, value: Option<&str>) -> Self {
+        self.defines.push((name.into(), value.map(str::to_owned)));
+        self
+    }
+
+    /// Overrides the output file name, see [`Self::output_name()`].
+    pub fn with_output_name(mut self, output_name: impl Into<String>) -> Self {
+        self.output_name = Some(output_name.into());
+        self
+    }
+
+    /// The file name of the output in the output directory.  Defaults to
+    /// `<file stem>.<entry point>.dxil`, or `.spv` when compiling with `-spirv`.
+    pub fn output_name(&self) -> String {
+        if let Some(output_name) = &self.output_name {
+            return output_name.clone();
+        }
+
+        let extension = if self.args.iter().any(|arg| arg == "-spirv") {
+            "spv"
+        } else {
+            "dxil"
+        };
+        let stem = self
+            .path
+            .file_stem()
+            .map_or_else(|| "shader".into(), |stem| stem.to_string_lossy());
+        format!("{}.{}.{}", stem, self.entry_point, extension)
+    }
+}
+
+/// A shader that was compiled by [`Build`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CompiledShader {
+    pub shader: Shader,
+    pub output: PathBuf,
+    /// The main source followed by every include that was read.
+    pub dependencies: Vec<PathBuf>,
+}
+
+/// Compiles a list of [`Shader`]s into `OUT_DIR`, for use in build scripts.
+///
+/// Unless disabled with [`Self::with_cargo_metadata()`], this prints a
+/// `cargo:rerun-if-changed` line for every main source and include, and forwards compiler
+/// warnings as `cargo:warning`.
+#[derive(Clone, Debug)]
+pub struct Build {
+    shaders: Vec<Shader>,
+    include_dirs: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
+    dxc_path: Option<PathBuf>,
+    cargo_metadata: bool,
+}
+
+impl Default for Build {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Build {
+    pub fn new() -> Self {
+        Self {
+            shaders: vec![],
+            include_dirs: vec![],
+            out_dir: None,
+            dxc_path: None,
+            cargo_metadata: true,
+        }
+    }
+
+    pub fn with_shader(mut self, shader: Shader) -> Self {
+        self.shaders.push(shader);
+        self
+    }
+
+    pub fn with_shaders<I: IntoIterator<Item = Shader>>(mut self, shaders: I) -> Self {
+        self.shaders.extend(shaders);
+        self
+    }
+
+    /// Adds a directory to the include search path of every shader, akin to `-I`.
+    pub fn with_include_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    /// Overrides the output directory, which defaults to `OUT_DIR`.
+    pub fn with_out_dir(mut self, out_dir: impl Into<PathBuf>) -> Self {
+        self.out_dir = Some(out_dir.into());
+        self
+    }
+
+    /// Path to the DXC library, see [`Dxc::new()`].
+    pub fn with_dxc_path(mut self, dxc_path: impl Into<PathBuf>) -> Self {
+        self.dxc_path = Some(dxc_path.into());
+        self
+    }
+
+    /// Whether to print `cargo:` instructions to standard output.  Enabled by default.
+    pub fn with_cargo_metadata(mut self, cargo_metadata: bool) -> Self {
+        self.cargo_metadata = cargo_metadata;
+        self
+    }
+
+    /// Compiles all shaders, and fails the build script with a readable error if any of them
+    /// fails to compile.
+    ///
+    /// # Panics
+    /// Panics with the formatted [`BuildError`] on failure, use [`Self::try_compile()`] to handle
+    /// it instead.
+    pub fn compile(&self) -> Vec<CompiledShader> {
+        match self.try_compile() {
+            Ok(compiled) => compiled,
+            Err(e) => panic!("\n\nerror occurred: {}\n\n", e),
+        }
+    }
+
+    /// Compiles all shaders, stopping at the first error.
+    pub fn try_compile(&self) -> Result<Vec<CompiledShader>, BuildError> {
+        let out_dir = match &self.out_dir {
+            Some(out_dir) => out_dir.clone(),
+            None => std::env::var_os("OUT_DIR")
+                .map(PathBuf::from)
+                .ok_or(BuildError::MissingOutDir)?,
+        };
+
+        let dxc = Dxc::new(self.dxc_path.clone()).map_err(BuildError::LoadDxc)?;
+        let compiler = dxc.create_compiler().map_err(BuildError::LoadDxc)?;
+        let library = dxc.create_library().map_err(BuildError::LoadDxc)?;
+
+        self.shaders
+            .iter()
+            .map(|shader| {
+                let source =
+                    std::fs::read_to_string(&shader.path).map_err(|inner| BuildError::Read {
+                        path: shader.path.clone(),
+                        inner,
+                    })?;
+                self.rerun_if_changed(&shader.path);
+
+                let job = CompileJob {
+                    source_name: shader.path.to_string_lossy().into_owned(),
+                    source,
+                    entry_point: shader.entry_point.clone(),
+                    target_profile: shader.target_profile.clone(),
+                    args: shader.args.clone(),
+                    defines: shader.defines.clone(),
+                };
+
+                // The source name is relative to the working directory, as are the paths DXC
+                // requests includes with
+                let mut include_handler = FileSystemIncludeHandler::new(".")
+                    .with_search_dirs(self.include_dirs.iter().cloned());
+                let result = job.compile(&compiler, &library, Some(&mut include_handler));
+
+                // Dependencies are reported on failure too, so that fixing an include reruns
+                // the build script
+                let mut dependencies = vec![shader.path.clone()];
+                for include in include_handler.resolved_includes() {
+                    if !dependencies.contains(&include.path) {
+                        self.rerun_if_changed(&include.path);
+                        dependencies.push(include.path.clone());
+                    }
+                }
+
+                let output = result.map_err(|inner| BuildError::Compile {
+                    path: shader.path.clone(),
+                    entry_point: shader.entry_point.clone(),
+                    target_profile: shader.target_profile.clone(),
+                    inner: Box::new(inner),
+                })?;
+
+                if let Some(messages) = &output.messages {
+                    self.warning(messages);
+                }
+
+                let output_path = out_dir.join(shader.output_name());
+                std::fs::write(&output_path, &output.blob).map_err(|inner| BuildError::Write {
+                    path: output_path.clone(),
+                    inner,
+                })?;
+
+                Ok(CompiledShader {
+                    shader: shader.clone(),
+                    output: output_path,
+                    dependencies,
+                })
+            })
+            .collect()
+    }
+
+    fn rerun_if_changed(&self, path: &Path) {
+        if self.cargo_metadata {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+
+    fn warning(&self, messages: &str) {
+        if self.cargo_metadata {
+            // Cargo only reads a single line per instruction
+            for line in messages.lines().filter(|line| !line.trim().is_empty()) {
+                println!("cargo:warning={}", line);
+            }
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Persistent on-disk cache for compilation results.
+
+use crate::pool::CompileJob;
+use crate::utils::{HassleError, OperationOutput, Result};
+use crate::wrapper::{DxcCompiler, DxcLibrary, IncludeError, IncludeHandler, IncludeRequest};
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryInto;
+use std::hash::Hasher;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const MAGIC: &[u8; 4] = b"HSLC";
+/// Bump whenever the key derivation or entry layout changes.
+const FORMAT_VERSION: u32 = 3;
+
+type Digest = [u8; 16];
+
+/// Computes a [`Digest`] from two differently seeded [`DefaultHasher`]s.  Their algorithm may
+/// change between Rust releases, which merely turns existing entries into misses.
+struct Context([DefaultHasher; 2]);
+
+impl Context {
+    fn new() -> Self {
+        let mut hashers = [DefaultHasher::new(), DefaultHasher::new()];
+        for (seed, hasher) in hashers.iter_mut().enumerate() {
+            hasher.write_u8(seed as u8);
+        }
+        Self(hashers)
+    }
+
+    fn consume(&mut self, data: impl AsRef<[u8]>) {
+        for hasher in &mut self.0 {
+            hasher.write(data.as_ref());
+        }
+    }
+
+    fn compute(self) -> Digest {
+        let mut digest = [0; 16];
+        for (chunk, hasher) in digest.chunks_exact_mut(8).zip(&self.0) {
+            chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+        digest
+    }
+}
+
+fn digest(data: &[u8]) -> Digest {
+    let mut ctx = Context::new();
+    ctx.consume(data);
+    ctx.compute()
+}
+
+fn hex(digest: &Digest) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether [`ShaderCache::compile()`] was served from the cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+}
+
+/// A stored compilation, together with the includes it was compiled against.
+#[derive(Debug, PartialEq)]
+struct CacheEntry {
+    /// Every include DXC requested, in order, with a digest of its contents or [`None`] if it
+    /// could not be loaded.
+    includes: Vec<(String, Option<Digest>)>,
+    output: OperationOutput,
+}
+
+impl CacheEntry {
+    fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
+        fn write_bytes(w: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+            w.write_all(&(bytes.len() as u64).to_le_bytes())?;
+            w.write_all(bytes)
+        }
+
+        w.write_all(MAGIC)?;
+        w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        w.write_all(&(self.includes.len() as u32).to_le_bytes())?;
+        for (name, digest) in &self.includes {
+            write_bytes(w, name.as_bytes())?;
+            match digest {
+                Some(digest) => {
+                    w.write_all(&[1])?;
+                    w.write_all(digest)?;
+                }
+                None => w.write_all(&[0])?,
+            }
+        }
+        match &self.output.messages {
+            Some(messages) => {
+                w.write_all(&[1])?;
+                write_bytes(w, messages.as_bytes())?;
+            }
+            None => w.write_all(&[0])?,
+        }
+        write_bytes(w, &self.output.blob)
+    }
+
+    /// Returns [`None`] if `data` is not a (complete) entry written by this version.
+    fn read(mut data: &[u8]) -> Option<Self> {
+        fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if data.len() < len {
+                return None;
+            }
+            let (head, tail) = data.split_at(len);
+            *data = tail;
+            Some(head)
+        }
+        fn take_u32(data: &mut &[u8]) -> Option<u32> {
+            Some(u32::from_le_bytes(take(data, 4)?.try_into().ok()?))
+        }
+        fn take_bytes<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+            let len = u64::from_le_bytes(take(data, 8)?.try_into().ok()?);
+            take(data, len.try_into().ok()?)
+        }
+        fn take_string(data: &mut &[u8]) -> Option<String> {
+            String::from_utf8(take_bytes(data)?.to_vec()).ok()
+        }
+
+        if take(&mut data, 4)? != MAGIC || take_u32(&mut data)? != FORMAT_VERSION {
+            return None;
+        }
+
+        let num_includes = take_u32(&mut data)?;
+        let mut includes = vec![];
+        for _ in 0..num_includes {
+            let name = take_string(&mut data)?;
+            let digest = match take(&mut data, 1)? {
+                [0] => None,
+                _ => Some(take(&mut data, 16)?.try_into().ok()?),
+            };
+            includes.push((name, digest));
+        }
+
+        let messages = match take(&mut data, 1)? {
+            [0] => None,
+            _ => Some(take_string(&mut data)?),
+        };
+        let blob = take_bytes(&mut data)?.to_vec();
+
+        if !data.is_empty() {
+            return None;
+        }
+        Some(Self {
+            includes,
+            output: OperationOutput { messages, blob },
+        })
+    }
+}
+
+/// Forwards to another handler while recording every request, with a digest of the includes
+/// it serves.
+struct HashingIncludeHandler<'a, H> {
+    inner: &'a mut H,
+    includes: Vec<(String, Option<Digest>)>,
+}
+
+impl<H: IncludeHandler> IncludeHandler for HashingIncludeHandler<'_, H> {
+    fn load(&mut self, request: &IncludeRequest<'_>) -> Result<Vec<u8>, IncludeError> {
+        let source = self.inner.load(request);
+        self.includes.push((
+            request.name.to_owned(),
+            source.as_ref().ok().map(|source| digest(source)),
+        ));
+        source
+    }
+
+    fn resolved_path(&self, request: &IncludeRequest<'_>) -> Option<PathBuf> {
+        self.inner.resolved_path(request)
+    }
+}
+
+/// Caches the output of [`DxcCompiler::compile()`] in a directory on disk, so that it can be
+/// reused across runs.
+///
+/// Entries are keyed by the main source, source name, entry point, target profile, arguments,
+/// defines and the compiler version and commit.  Every entry additionally records each include
+/// DXC requested during compilation, together with the contents it was served or the fact that
+/// it was not found.  On lookup these requests are replayed against a fresh include handler: an
+/// entry is only used if every include still has the same contents and every include that was
+/// missing is still missing, such as a header that now shadows a later search directory.
+///
+/// Only successful compilations are cached.
+#[derive(Clone, Debug)]
+pub struct ShaderCache {
+    dir: PathBuf,
+}
+
+impl ShaderCache {
+    /// Opens or creates the cache in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Removes every entry from the cache.
+    pub fn clear(&self) -> Result<()> {
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "bin") {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles `job`, or returns the cached output if it is still up-to-date.
+    ///
+    /// `create_include_handler` is called for a fresh handler to validate a cached entry, and for
+    /// another one if the shader has to be recompiled, so that neither sees the other's includes.
+    /// The handler that served the last round of includes is returned, which has loaded the same
+    /// includes on a hit as on a miss: a [`crate::include::DependencyTracker`] reports the
+    /// dependencies of the shader either way.
+    pub fn compile<H: IncludeHandler>(
+        &self,
+        compiler: &DxcCompiler,
+        library: &DxcLibrary,
+        job: &CompileJob,
+        mut create_include_handler: impl FnMut() -> H,
+    ) -> Result<(OperationOutput, CacheStatus, H)> {
+        let key = Self::key(compiler, job)?;
+        let path = self.dir.join(format!("{}.bin", hex(&key)));
+
+        if let Some(entry) = Self::load_entry(&path) {
+            let mut include_handler = create_include_handler();
+            if Self::is_up_to_date(&entry, &mut include_handler) {
+                return Ok((entry.output, CacheStatus::Hit, include_handler));
+            }
+        }
+
+        let mut include_handler = create_include_handler();
+        let mut hashing_handler = HashingIncludeHandler {
+            inner: &mut include_handler,
+            includes: vec![],
+        };
+        let output = job.compile(compiler, library, Some(&mut hashing_handler))?;
+
+        let entry = CacheEntry {
+            includes: hashing_handler.includes,
+            output,
+        };
+        self.store_entry(&path, &entry)?;
+
+        Ok((entry.output, CacheStatus::Miss, include_handler))
+    }
+
+    fn key(compiler: &DxcCompiler, job: &CompileJob) -> Result<Digest> {
+        let (major, minor) = compiler.version()?;
+        // Not every DXC build exposes IDxcVersionInfo2
+        let (commit_count, commit_hash) = compiler.commit_info().unwrap_or_default();
+
+        let mut ctx = Context::new();
+        let mut consume = |data: &[u8]| {
+            ctx.consume((data.len() as u64).to_le_bytes());
+            ctx.consume(data);
+        };
+
+        consume(&FORMAT_VERSION.to_le_bytes());
+        consume(&major.to_le_bytes());
+        consume(&minor.to_le_bytes());
+        consume(&commit_count.to_le_bytes());
+        consume(commit_hash.as_bytes());
+        consume(job.source_name.as_bytes());
+        consume(job.source.as_bytes());
+        consume(job.entry_point.as_bytes());
+        consume(job.target_profile.as_bytes());
+        consume(&(job.args.len() as u64).to_le_bytes());
+        for arg in &job.args {
+            consume(arg.as_bytes());
+        }
+        consume(&(job.defines.len() as u64).to_le_bytes());
+        for (name, value) in &job.defines {
+            consume(name.as_bytes());
+            match value {
+                Some(value) => consume(value.as_bytes()),
+                None => consume(&[]),
+            }
+        }
+
+        Ok(ctx.compute())
+    }
+
+    fn load_entry(path: &Path) -> Option<CacheEntry> {
+        let mut data = vec![];
+        std::fs::File::open(path)
+            .ok()?
+            .read_to_end(&mut data)
+            .ok()?;
+        CacheEntry::read(&data)
+    }
+
+    /// Replays the include requests of `entry` in order.  As long as every request has the
+    /// same outcome, DXC would have made the exact same requests when recompiling.
+    fn is_up_to_date(entry: &CacheEntry, include_handler: &mut impl IncludeHandler) -> bool {
+        let mut loaded = vec![];
+        for (name, expected) in &entry.includes {
+            let request = IncludeRequest {
+                name,
+                loaded: &loaded,
+            };
+            match (include_handler.load(&request), expected) {
+                (Ok(source), Some(expected)) if digest(&source) == *expected => {
+                    loaded.push(name.clone())
+                }
+                (Err(_), None) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    fn store_entry(&self, path: &Path, entry: &CacheEntry) -> Result<()> {
+        // Write to a temporary file first, so that concurrent readers never observe a partially
+        // written entry
+        static NEXT_TMP_ID: AtomicUsize = AtomicUsize::new(0);
+        let tmp_path = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            NEXT_TMP_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = (|| {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+            entry.write(&mut file)?;
+            file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            std::fs::rename(&tmp_path, path)
+        })();
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        result.map_err(HassleError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::include::VirtualIncludeHandler;
+
+    fn entry() -> CacheEntry {
+        CacheEntry {
+            includes: vec![
+                ("./common.hlsli".to_owned(), Some(digest(b"// common"))),
+                ("./missing.hlsli".to_owned(), None),
+            ],
+            output: OperationOutput {
+                messages: Some("warning: unused variable".to_owned()),
+                blob: vec![1, 2, 3, 4],
+            },
+        }
+    }
+
+    #[test]
+    fn entry_round_trips() {
+        let mut data = vec![];
+        entry().write(&mut data).unwrap();
+        assert_eq!(CacheEntry::read(&data), Some(entry()));
+
+        let without_messages = CacheEntry {
+            includes: vec![],
+            output: OperationOutput {
+                messages: None,
+                blob: vec![],
+            },
+        };
+        let mut data = vec![];
+        without_messages.write(&mut data).unwrap();
+        assert_eq!(CacheEntry::read(&data), Some(without_messages));
+    }
+
+    #[test]
+    fn truncated_or_foreign_entries_are_rejected() {
+        let mut data = vec![];
+        entry().write(&mut data).unwrap();
+
+        for len in 0..data.len() {
+            assert_eq!(CacheEntry::read(&data[..len]), None);
+        }
+
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert_eq!(CacheEntry::read(&trailing), None);
+
+        let mut old_version = data;
+        old_version[4..8].copy_from_slice(&(FORMAT_VERSION - 1).to_le_bytes());
+        assert_eq!(CacheEntry::read(&old_version), None);
+    }
+
+    #[test]
+    fn digests_depend_on_every_byte() {
+        assert_eq!(digest(b"// common"), digest(b"// common"));
+        assert_ne!(digest(b"// common"), digest(b"// commoN"));
+        assert_ne!(digest(b""), digest(b"\0"));
+
+        let digest = digest(b"float4");
+        assert_ne!(digest[..8], digest[8..]);
+    }
+
+    #[test]
+    fn replay_detects_changed_and_new_includes() {
+        let unchanged = VirtualIncludeHandler::new().with_file("common.hlsli", "// common");
+        assert!(ShaderCache::is_up_to_date(&entry(), &mut unchanged.clone()));
+
+        let mut changed = VirtualIncludeHandler::new().with_file("common.hlsli", "// changed");
+        assert!(!ShaderCache::is_up_to_date(&entry(), &mut changed));
+
+        let mut now_found = unchanged.with_file("missing.hlsli", "// found");
+        assert!(!ShaderCache::is_up_to_date(&entry(), &mut now_found));
+    }
+}
@@ -0,0 +1,480 @@
+//! Loading several DXC releases side by side and comparing their output.
+//!
+//! ```no_run
+//! use hassle_rs::{CompileJob, DxcVersions};
+//!
+//! let versions = DxcVersions::load(["dxc-1.7/lib", "dxc-1.8/lib"])?;
+//! for dxc in versions.iter() {
+//!     println!("{}", dxc);
+//! }
+//!
+//! let job = CompileJob::new(
+//!     "copy.hlsl",
+//!     include_str!("../examples/copy.hlsl"),
+//!     "copyCs",
+//!     "cs_6_5",
+//! );
+//! for difference in versions.compare(&job).differences() {
+//!     println!("{}", difference);
+//! }
+//! # Ok::<(), hassle_rs::HassleError>(())
+//! ```
+
+use crate::diagnostics::Diagnostic;
+use crate::pool::CompileJob;
+use crate::utils::{DefaultIncludeHandler, OperationOutput, Result};
+use crate::wrapper::{Dxc, DxcCompilerVersion, IncludeHandler};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A loaded DXC library, tagged with the version it reports.
+#[derive(Debug)]
+pub struct VersionedDxc {
+    dxc: Dxc,
+    version: DxcCompilerVersion,
+    commit_info: Option<(u32, String)>,
+}
+
+impl VersionedDxc {
+    /// Loads the library at `lib_path`, see [`Dxc::new()`], and queries its version.
+    pub fn load(lib_path: impl Into<PathBuf>) -> Result<Self> {
+        Self::new(Dxc::new(Some(lib_path.into()))?)
+    }
+
+    /// Queries the version of an already loaded library.
+    pub fn new(dxc: Dxc) -> Result<Self> {
+        let compiler = dxc.create_compiler()?;
+        let version = compiler.version()?;
+        // Older releases do not implement `IDxcVersionInfo2`
+        let commit_info = compiler.commit_info().ok();
+        drop(compiler);
+
+        Ok(Self {
+            dxc,
+            version,
+            commit_info,
+        })
+    }
+
+    pub fn dxc(&self) -> &Dxc {
+        &self.dxc
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dxc.path()
+    }
+
+    pub fn version(&self) -> DxcCompilerVersion {
+        self.version
+    }
+
+    /// The number of commits and the commit hash the library was built from, if it reports it.
+    pub fn commit_info(&self) -> Option<(u32, &str)> {
+        self.commit_info
+            .as_ref()
+            .map(|(count, hash)| (*count, hash.as_str()))
+    }
+
+    /// Compiles `job` with this library, resolving includes with `include_handler`.
+    pub fn compile(
+        &self,
+        job: &CompileJob,
+        include_handler: Option<&mut dyn IncludeHandler>,
+    ) -> Result<OperationOutput> {
+        let compiler = self.dxc.create_compiler()?;
+        let library = self.dxc.create_library()?;
+        job.compile(&compiler, &library, include_handler)
+    }
+}
+
+/// Formats as `1.7 (commit 4009, abcdef0) at path/to/libdxcompiler.so`.
+impl fmt::Display for VersionedDxc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.version.0, self.version.1)?;
+        if let Some((count, hash)) = self.commit_info() {
+            write!(f, " (commit {}, {})", count, hash)?;
+        }
+        write!(f, " at {}", self.path().display())
+    }
+}
+
+/// Libraries are identified by the path they were loaded from.
+impl PartialEq for VersionedDxc {
+    fn eq(&self, other: &Self) -> bool {
+        self.path() == other.path()
+    }
+}
+
+impl Eq for VersionedDxc {}
+
+impl std::hash::Hash for VersionedDxc {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.path().hash(state)
+    }
+}
+
+type IncludeHandlerFactory = dyn Fn(&CompileJob) -> Box<dyn IncludeHandler>;
+
+/// A set of DXC libraries loaded into the same process, to compare their output.
+///
+/// Every library must be loaded from a different file, as the platform loader returns the
+/// already loaded library when the same file is opened twice.
+pub struct DxcVersions {
+    versions: Vec<VersionedDxc>,
+    include_handler: Box<IncludeHandlerFactory>,
+}
+
+impl DxcVersions {
+    /// Loads every library in `lib_paths`, failing when any of them cannot be loaded.
+    pub fn load<I: IntoIterator<Item = P>, P: Into<PathBuf>>(lib_paths: I) -> Result<Self> {
+        let versions = lib_paths
+            .into_iter()
+            .map(VersionedDxc::load)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(versions))
+    }
+
+    /// Resolves includes from the filesystem like [`crate::compile_hlsl()`].
+    pub fn new(versions: Vec<VersionedDxc>) -> Self {
+        Self {
+            versions,
+            include_handler: Box::new(|_| Box::new(DefaultIncludeHandler {})),
+        }
+    }
+
+    /// Sets the function that creates a fresh include handler for every compilation.
+    pub fn with_include_handler<F, H>(mut self, create_include_handler: F) -> Self
+    where
+        F: Fn(&CompileJob) -> H + 'static,
+        H: IncludeHandler + 'static,
+    {
+        self.include_handler = Box::new(move |job| Box::new(create_include_handler(job)));
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VersionedDxc> {
+        self.versions.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    /// Compiles `job` with every library, in the order they were loaded.
+    pub fn compare(&self, job: &CompileJob) -> CompileComparison<'_> {
+        let results = self
+            .versions
+            .iter()
+            .map(|dxc| {
+                let mut include_handler = (self.include_handler)(job);
+                VersionResult {
+                    dxc,
+                    result: dxc.compile(job, Some(&mut *include_handler)),
+                }
+            })
+            .collect();
+        CompileComparison { results }
+    }
+}
+
+/// The result of compiling a job with one of the libraries in [`DxcVersions`].
+#[derive(Debug)]
+pub struct VersionResult<'a> {
+    pub dxc: &'a VersionedDxc,
+    pub result: Result<OperationOutput>,
+}
+
+impl VersionResult<'_> {
+    /// Diagnostics of both successful and failed compilations.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match &self.result {
+            Ok(output) => output.diagnostics(),
+            Err(e) => e.diagnostics(),
+        }
+    }
+
+    /// Size of the compiled output, or [`None`] if compilation failed.
+    pub fn output_size(&self) -> Option<usize> {
+        self.result.as_ref().ok().map(|output| output.blob.len())
+    }
+}
+
+/// A way in which the result of one library differs from that of the first library.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Difference<'a> {
+    /// Compilation succeeded with one library but not with the other.
+    Success {
+        baseline: &'a VersionedDxc,
+        other: &'a VersionedDxc,
+        baseline_succeeded: bool,
+    },
+    /// Diagnostics that are only reported by one of the libraries.
+    Diagnostics {
+        baseline: &'a VersionedDxc,
+        other: &'a VersionedDxc,
+        only_in_baseline: Vec<Diagnostic>,
+        only_in_other: Vec<Diagnostic>,
+    },
+    /// Both libraries succeeded, but produced output of a different size.
+    OutputSize {
+        baseline: &'a VersionedDxc,
+        other: &'a VersionedDxc,
+        baseline_size: usize,
+        other_size: usize,
+    },
+}
+
+impl fmt::Display for Difference<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Success {
+                baseline,
+                other,
+                baseline_succeeded,
+            } => {
+                let (succeeded, failed) = if *baseline_succeeded {
+                    (baseline, other)
+                } else {
+                    (other, baseline)
+                };
+                write!(f, "succeeds with {} but fails with {}", succeeded, failed)
+            }
+            Self::Diagnostics {
+                baseline,
+                other,
+                only_in_baseline,
+                only_in_other,
+            } => {
+                write!(f, "diagnostics differ between {} and {}", baseline, other)?;
+                for diagnostic in only_in_baseline {
+                    write!(f, "\n  - {}", diagnostic)?;
+                }
+                for diagnostic in only_in_other {
+                    write!(f, "\n  + {}", diagnostic)?;
+                }
+                Ok(())
+            }
+            Self::OutputSize {
+                baseline,
+                other,
+                baseline_size,
+                other_size,
+            } => write!(
+                f,
+                "output is {} bytes with {} but {} bytes with {}",
+                baseline_size, baseline, other_size, other
+            ),
+        }
+    }
+}
+
+/// The results of [`DxcVersions::compare()`].
+#[derive(Debug)]
+pub struct CompileComparison<'a> {
+    pub results: Vec<VersionResult<'a>>,
+}
+
+impl<'a> CompileComparison<'a> {
+    /// Compares the result of every library against that of the first library.
+    pub fn differences(&self) -> Vec<Difference<'a>> {
+        let (baseline, others) = match self.results.split_first() {
+            Some(split) => split,
+            None => return vec![],
+        };
+        let baseline_diagnostics = baseline.diagnostics();
+
+        let mut differences = vec![];
+        for other in others {
+            if baseline.result.is_ok() != other.result.is_ok() {
+                differences.push(Difference::Success {
+                    baseline: baseline.dxc,
+                    other: other.dxc,
+                    baseline_succeeded: baseline.result.is_ok(),
+                });
+            }
+
+            let other_diagnostics = other.diagnostics();
+            let only_in_baseline = baseline_diagnostics
+                .iter()
+                .filter(|diagnostic| !other_diagnostics.contains(diagnostic))
+                .cloned()
+                .collect::<Vec<_>>();
+            let only_in_other = other_diagnostics
+                .iter()
+                .filter(|diagnostic| !baseline_diagnostics.contains(diagnostic))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !only_in_baseline.is_empty() || !only_in_other.is_empty() {
+                differences.push(Difference::Diagnostics {
+                    baseline: baseline.dxc,
+                    other: other.dxc,
+                    only_in_baseline,
+                    only_in_other,
+                });
+            }
+
+            if let (Some(baseline_size), Some(other_size)) =
+                (baseline.output_size(), other.output_size())
+            {
+                if baseline_size != other_size {
+                    differences.push(Difference::OutputSize {
+                        baseline: baseline.dxc,
+                        other: other.dxc,
+                        baseline_size,
+                        other_size,
+                    });
+                }
+            }
+        }
+        differences
+    }
+
+    /// Whether [`Self::differences()`] is empty.  Outputs of the same size are not compared
+    /// byte by byte, as they typically embed a hash of the compiler version.
+    pub fn is_identical(&self) -> bool {
+        self.differences().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::parse_diagnostics;
+    use crate::os::HRESULT;
+    use crate::utils::HassleError;
+
+    fn versioned(path: &str, minor: u32) -> VersionedDxc {
+        VersionedDxc {
+            dxc: Dxc::without_library(path),
+            version: (1, minor),
+            commit_info: None,
+        }
+    }
+
+    fn output(messages: Option<&str>, blob: &[u8]) -> Result<OperationOutput> {
+        Ok(OperationOutput {
+            messages: messages.map(str::to_owned),
+            blob: blob.to_vec(),
+        })
+    }
+
+    fn failure(messages: &str) -> Result<OperationOutput> {
+        Err(HassleError::OperationError(
+            HRESULT(0x8000_4005_u32 as i32),
+            messages.to_owned(),
+        ))
+    }
+
+    fn compare<'a>(
+        baseline: (&'a VersionedDxc, Result<OperationOutput>),
+        other: (&'a VersionedDxc, Result<OperationOutput>),
+    ) -> CompileComparison<'a> {
+        CompileComparison {
+            results: vec![
+                VersionResult {
+                    dxc: baseline.0,
+                    result: baseline.1,
+                },
+                VersionResult {
+                    dxc: other.0,
+                    result: other.1,
+                },
+            ],
+        }
+    }
+
+    const WARNING: &str = "shader.hlsl:2:5: warning: unused variable 'x'";
+    const ERROR: &str = "shader.hlsl:3:1: error: use of undeclared identifier 'foo'";
+
+    #[test]
+    fn identical_results() {
+        let (old, new) = (versioned("dxc-1.7", 7), versioned("dxc-1.8", 8));
+
+        let comparison = compare(
+            (&old, output(Some(WARNING), &[1, 2, 3])),
+            (&new, output(Some(WARNING), &[1, 2, 3])),
+        );
+        assert!(comparison.is_identical());
+
+        // Outputs of the same size are considered identical, as they embed a version hash
+        let comparison = compare(
+            (&old, output(None, &[1, 2, 3])),
+            (&new, output(None, &[4, 5, 6])),
+        );
+        assert!(comparison.is_identical());
+
+        let comparison = compare((&old, failure(ERROR)), (&new, failure(ERROR)));
+        assert!(comparison.is_identical());
+
+        assert!(CompileComparison { results: vec![] }.is_identical());
+    }
+
+    #[test]
+    fn differing_blobs() {
+        let (old, new) = (versioned("dxc-1.7", 7), versioned("dxc-1.8", 8));
+
+        let comparison = compare(
+            (&old, output(None, &[1, 2, 3])),
+            (&new, output(None, &[1, 2, 3, 4])),
+        );
+        assert_eq!(
+            comparison.differences(),
+            [Difference::OutputSize {
+                baseline: &old,
+                other: &new,
+                baseline_size: 3,
+                other_size: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn differing_messages() {
+        let (old, new) = (versioned("dxc-1.7", 7), versioned("dxc-1.8", 8));
+
+        let comparison = compare(
+            (&old, output(Some(WARNING), &[1, 2, 3])),
+            (&new, output(None, &[1, 2, 3])),
+        );
+        assert_eq!(
+            comparison.differences(),
+            [Difference::Diagnostics {
+                baseline: &old,
+                other: &new,
+                only_in_baseline: parse_diagnostics(WARNING),
+                only_in_other: vec![],
+            }]
+        );
+    }
+
+    #[test]
+    fn success_versus_failure() {
+        let (old, new) = (versioned("dxc-1.7", 7), versioned("dxc-1.8", 8));
+
+        let comparison = compare((&old, output(None, &[1, 2, 3])), (&new, failure(ERROR)));
+        assert_eq!(
+            comparison.differences(),
+            [
+                Difference::Success {
+                    baseline: &old,
+                    other: &new,
+                    baseline_succeeded: true,
+                },
+                Difference::Diagnostics {
+                    baseline: &old,
+                    other: &new,
+                    only_in_baseline: vec![],
+                    only_in_other: parse_diagnostics(ERROR),
+                },
+            ]
+        );
+        assert_eq!(
+            comparison.differences()[0].to_string(),
+            "succeeds with 1.7 at dxc-1.7 but fails with 1.8 at dxc-1.8"
+        );
+    }
+}
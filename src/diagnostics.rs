@@ -0,0 +1,656 @@
+//! Structured diagnostics parsed from the text DXC writes to its error buffer.
+
+use crate::include::{normalize_path, IncludeSource};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Remark,
+    Warning,
+    Error,
+    Fatal,
+}
+
+impl Severity {
+    /// The severities as spelled by DXC, longest first so that `fatal error` is matched before
+    /// `error`.
+    const KEYWORDS: [(&'static str, Severity); 5] = [
+        ("fatal error", Severity::Fatal),
+        ("warning", Severity::Warning),
+        ("remark", Severity::Remark),
+        ("error", Severity::Error),
+        ("note", Severity::Note),
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Note => "note",
+            Self::Remark => "remark",
+            Self::Warning => "warning",
+            Self::Error => "error",
+            Self::Fatal => "fatal error",
+        }
+    }
+
+    /// Returns `true` for [`Self::Error`] and [`Self::Fatal`].
+    pub fn is_error(self) -> bool {
+        self >= Self::Error
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A file and line from an `In file included from` chain.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IncludeLocation {
+    pub file: String,
+    pub line: u32,
+}
+
+/// A single error, warning or note from DXC, see [`parse_diagnostics()`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    /// [`None`] for diagnostics that are not tied to a source file, such as command line errors.
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+    /// The warning option that controls this diagnostic, such as `-Wconversion`.
+    pub option: Option<String>,
+    /// `note:` diagnostics that directly follow this one.
+    pub notes: Vec<Diagnostic>,
+    /// The chain of includes that led to [`Self::file`], outermost file first.
+    pub include_stack: Vec<IncludeLocation>,
+    /// The lines DXC printed below the message, typically the offending source line and a
+    /// caret.
+    pub snippet: Option<String>,
+}
+
+impl Diagnostic {
+    /// Parses a `<location>: <severity>: <message>` line.
+    fn parse_line(line: &str) -> Option<Self> {
+        // Find the earliest severity keyword, either at the start of the line or after a location
+        let (start, len, severity) = Severity::KEYWORDS
+            .iter()
+            .filter_map(|&(keyword, severity)| {
+                let at_start = if line
+                    .strip_prefix(keyword)
+                    .map_or(false, |rest| rest.starts_with(": "))
+                {
+                    Some(0)
+                } else {
+                    None
+                };
+                at_start
+                    .or_else(|| line.find(&format!(": {}: ", keyword)).map(|i| i + 2))
+                    .map(|start| (start, keyword.len(), severity))
+            })
+            .min_by_key(|&(start, len, _)| (start, std::cmp::Reverse(len)))?;
+
+        let location = line[..start].strip_suffix(": ").unwrap_or("");
+        let message = &line[start + len + ": ".len()..];
+
+        let (file, line, column) = Self::parse_location(location);
+
+        let (message, option) = match message.strip_suffix(']').and_then(|m| m.rsplit_once(" [-")) {
+            Some((message, option)) => (message, Some(format!("-{}", option))),
+            None => (message, None),
+        };
+
+        Some(Self {
+            file,
+            line,
+            column,
+            severity,
+            message: message.to_owned(),
+            option,
+            notes: vec![],
+            include_stack: vec![],
+            snippet: None,
+        })
+    }
+
+    /// Splits `file:line:column`, `file:line` or `file` from the right, so that Windows drive
+    /// letters are left intact.
+    fn parse_location(location: &str) -> (Option<String>, Option<u32>, Option<u32>) {
+        if location.is_empty() {
+            return (None, None, None);
+        }
+
+        let mut parts = location.rsplitn(3, ':');
+        let last = parts.next().and_then(|s| s.parse().ok());
+        let middle = parts.next();
+        let first = parts.next();
+
+        match (first, middle, last) {
+            (Some(file), Some(line), Some(column)) => match line.parse() {
+                Ok(line) => (Some(file.to_owned()), Some(line), Some(column)),
+                Err(_) => (Some(format!("{}:{}", file, line)), Some(column), None),
+            },
+            (None, Some(file), Some(line)) => (Some(file.to_owned()), Some(line), None),
+            _ => (Some(location.to_owned()), None, None),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+            if let Some(line) = self.line {
+                write!(f, "{}:", line)?;
+            }
+            if let Some(column) = self.column {
+                write!(f, "{}:", column)?;
+            }
+            f.write_str(" ")?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)?;
+        if let Some(option) = &self.option {
+            write!(f, " [{}]", option)?;
+        }
+        for note in &self.notes {
+            write!(f, "\n{}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the messages DXC writes to its error buffer, as found in
+/// [`crate::OperationOutput::messages`] and [`crate::HassleError::OperationError`].
+///
+/// Lines that are neither a diagnostic nor part of an include chain are attached to the
+/// preceding diagnostic as its [`Diagnostic::snippet`], or skipped if there is none.
+///
+/// ```
+/// use hassle_rs::{parse_diagnostics, Severity};
+///
+/// let messages = "\
+/// In file included from shader.hlsl:2:
+/// ./common.hlsli:7:12: warning: implicit truncation of vector type [-Wconversion]
+///     return v;
+///            ^
+/// shader.hlsl:10:5: error: use of undeclared identifier 'foo'
+///     foo();
+///     ^
+/// shader.hlsl:4:6: note: 'bar' declared here
+/// ";
+///
+/// let diagnostics = parse_diagnostics(messages);
+/// assert_eq!(diagnostics.len(), 2);
+///
+/// let warning = &diagnostics[0];
+/// assert_eq!(warning.file.as_deref(), Some("./common.hlsli"));
+/// assert_eq!((warning.line, warning.column), (Some(7), Some(12)));
+/// assert_eq!(warning.severity, Severity::Warning);
+/// assert_eq!(warning.message, "implicit truncation of vector type");
+/// assert_eq!(warning.option.as_deref(), Some("-Wconversion"));
+/// assert_eq!(warning.include_stack[0].file, "shader.hlsl");
+/// assert_eq!(warning.snippet.as_deref(), Some("    return v;\n           ^"));
+///
+/// let error = &diagnostics[1];
+/// assert_eq!(error.severity, Severity::Error);
+/// assert!(error.include_stack.is_empty());
+/// assert_eq!(error.notes[0].message, "'bar' declared here");
+/// ```
+pub fn parse_diagnostics(messages: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+    // `In file included from` lines that were not yet attached to a diagnostic
+    let mut pending_include_stack = vec![];
+    // Clang only prints the include chain when it changes, so later diagnostics in the same file
+    // inherit the chain of the last diagnostic
+    let mut current_file = None;
+    let mut include_stack = vec![];
+    // Whether the last diagnostic was a note attached to the previous diagnostic
+    let mut in_note = false;
+
+    for line in messages.lines() {
+        if let Some(location) = line
+            .strip_prefix("In file included from ")
+            .and_then(|l| l.strip_suffix(':'))
+        {
+            if let (Some(file), Some(line), _) = Diagnostic::parse_location(location) {
+                pending_include_stack.push(IncludeLocation { file, line });
+            }
+            continue;
+        }
+
+        if let Some(mut diagnostic) = Diagnostic::parse_line(line) {
+            if !pending_include_stack.is_empty() {
+                include_stack = std::mem::take(&mut pending_include_stack);
+            } else if diagnostic.file != current_file {
+                include_stack.clear();
+            }
+            current_file = diagnostic.file.clone();
+            diagnostic.include_stack = include_stack.clone();
+            in_note = diagnostic.severity == Severity::Note && !diagnostics.is_empty();
+            match diagnostics.last_mut() {
+                Some(parent) if in_note => parent.notes.push(diagnostic),
+                _ => diagnostics.push(diagnostic),
+            }
+            continue;
+        }
+
+        let last = diagnostics.last_mut().map(|last| match in_note {
+            true => last.notes.last_mut().unwrap(),
+            false => last,
+        });
+        if let Some(last) = last {
+            let snippet = last.snippet.get_or_insert_with(String::new);
+            if !snippet.is_empty() {
+                snippet.push('\n');
+            }
+            snippet.push_str(line);
+        }
+    }
+
+    // Drop trailing blank lines that separate diagnostics
+    for diagnostic in &mut diagnostics {
+        trim_snippet(&mut diagnostic.snippet);
+        for note in &mut diagnostic.notes {
+            trim_snippet(&mut note.snippet);
+        }
+    }
+
+    diagnostics
+}
+
+fn trim_snippet(snippet: &mut Option<String>) {
+    if let Some(s) = snippet {
+        let len = s.trim_end_matches(['\n', ' ']).len();
+        s.truncate(len);
+        if s.is_empty() {
+            *snippet = None;
+        }
+    }
+}
+
+type SourceProvider<'a> = dyn FnMut(&str) -> Option<String> + 'a;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BLUE: &str = "\x1b[1;34m";
+
+fn severity_color(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Note => "\x1b[1;32m",
+        Severity::Remark => "\x1b[1;36m",
+        Severity::Warning => "\x1b[1;33m",
+        Severity::Error | Severity::Fatal => "\x1b[1;31m",
+    }
+}
+
+/// Renders [`Diagnostic`]s with source snippets, in the style of `rustc`:
+///
+/// ```text
+/// error: use of undeclared identifier 'foo'
+///   --> ./common.hlsli:10:5
+///    |
+/// 10 |     foo();
+///    |     ^^^
+///    = note: included from shader.hlsl:2
+/// ```
+///
+/// Source text is looked up by the file name in the diagnostic, first among the files passed to
+/// [`Self::with_source()`] and then through the include handler passed to
+/// [`Self::with_include_handler()`].  As DXC does not load the main source file through the
+/// include handler, it should be passed to [`Self::with_source()`].  When a file cannot be
+/// loaded, the snippet DXC printed itself is shown instead.
+///
+/// ```
+/// use hassle_rs::{parse_diagnostics, DiagnosticRenderer};
+///
+/// let source = "void main() {\n    foo();\n}\n";
+/// let diagnostics = parse_diagnostics("shader.hlsl:2:5: error: use of undeclared identifier 'foo'");
+///
+/// let rendered = DiagnosticRenderer::new()
+///     .with_source("shader.hlsl", source)
+///     .render_all(&diagnostics);
+/// assert_eq!(
+///     rendered,
+///     "error: use of undeclared identifier 'foo'
+///  --> shader.hlsl:2:5
+///   |
+/// 2 |     foo();
+///   |     ^^^
+/// "
+/// );
+/// ```
+pub struct DiagnosticRenderer<'a> {
+    source_provider: Option<Box<SourceProvider<'a>>>,
+    /// Source text by normalized file name, or [`None`] if it failed to load.
+    sources: HashMap<String, Option<String>>,
+    colors: bool,
+}
+
+impl Default for DiagnosticRenderer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> DiagnosticRenderer<'a> {
+    pub fn new() -> Self {
+        Self {
+            source_provider: None,
+            sources: HashMap::new(),
+            colors: false,
+        }
+    }
+
+    /// Loads source files through `include_handler`, typically the same handler that was used
+    /// for the compilation, so that headers are read from wherever they were found.
+    ///
+    /// ```no_run
+    /// use hassle_rs::{DiagnosticRenderer, FileSystemIncludeHandler};
+    ///
+    /// let mut include_handler = FileSystemIncludeHandler::new(".").with_search_dir("include");
+    /// // ... compile with `Some(&mut include_handler)` ...
+    /// let renderer = DiagnosticRenderer::new().with_include_handler(&include_handler);
+    /// ```
+    pub fn with_include_handler<H: IncludeSource + ?Sized>(self, include_handler: &'a H) -> Self {
+        self.with_source_provider(move |file| {
+            include_handler
+                .read_source(file)
+                .map(|data| String::from_utf8_lossy(&data).into_owned())
+        })
+    }
+
+    /// Loads the text of files that were not passed to [`Self::with_source()`] through
+    /// `source_provider` instead of an include handler, by the file name in the diagnostic.
+    /// Each file is requested at most once.
+    pub fn with_source_provider<F: FnMut(&str) -> Option<String> + 'a>(
+        mut self,
+        source_provider: F,
+    ) -> Self {
+        self.source_provider = Some(Box::new(source_provider));
+        self
+    }
+
+    /// Provides the text of `name`, such as the main source file.
+    pub fn with_source(mut self, name: &str, text: impl Into<String>) -> Self {
+        self.sources.insert(normalize_path(name), Some(text.into()));
+        self
+    }
+
+    /// Whether to emit ANSI color codes.  Disabled by default.
+    pub fn with_colors(mut self, colors: bool) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    /// Renders every diagnostic followed by an empty line.
+    pub fn render_all(&mut self, diagnostics: &[Diagnostic]) -> String {
+        let mut out = String::new();
+        for (i, diagnostic) in diagnostics.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            self.render_into(&mut out, diagnostic);
+        }
+        out
+    }
+
+    /// Renders a diagnostic and its notes.
+    pub fn render(&mut self, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        self.render_into(&mut out, diagnostic);
+        out
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.colors {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    }
+
+    fn render_into(&mut self, out: &mut String, diagnostic: &Diagnostic) {
+        let color = severity_color(diagnostic.severity);
+
+        let mut message = diagnostic.message.clone();
+        if let Some(option) = &diagnostic.option {
+            message.push_str(&format!(" [{}]", option));
+        }
+        out.push_str(&format!(
+            "{}{}\n",
+            self.paint(color, &format!("{}:", diagnostic.severity)),
+            self.paint(BOLD, &format!(" {}", message))
+        ));
+
+        if let Some(file) = &diagnostic.file {
+            let line_number = diagnostic.line.map(|l| l.to_string()).unwrap_or_default();
+            let pad = " ".repeat(line_number.len().max(1));
+            let gutter = self.paint(BLUE, &format!("{} |", pad));
+
+            let mut location = file.clone();
+            for n in diagnostic.line.iter().chain(&diagnostic.column) {
+                location.push_str(&format!(":{}", n));
+            }
+            out.push_str(&format!(
+                "{}{} {}\n",
+                pad,
+                self.paint(BLUE, "-->"),
+                location
+            ));
+
+            let source_line = diagnostic
+                .line
+                .and_then(|line| self.source_line(file, line));
+            if let Some(source_line) = source_line {
+                out.push_str(&format!("{}\n", gutter));
+                out.push_str(&format!(
+                    "{} {}\n",
+                    self.paint(BLUE, &format!("{} |", line_number)),
+                    source_line
+                ));
+                if let Some(column) = diagnostic.column {
+                    let (indent, len) = underline(&source_line, column, &diagnostic.snippet);
+                    out.push_str(&format!(
+                        "{} {}{}\n",
+                        gutter,
+                        indent,
+                        self.paint(color, &"^".repeat(len))
+                    ));
+                }
+            } else if let Some(snippet) = &diagnostic.snippet {
+                out.push_str(&format!("{}\n", gutter));
+                for line in snippet.lines() {
+                    out.push_str(&format!("{} {}\n", gutter, line));
+                }
+            }
+
+            // Innermost include first, reading as "this file was included from ..."
+            for include in diagnostic.include_stack.iter().rev() {
+                out.push_str(&format!(
+                    "{} {} included from {}:{}\n",
+                    pad,
+                    self.paint(BLUE, "="),
+                    include.file,
+                    include.line
+                ));
+            }
+        }
+
+        for note in &diagnostic.notes {
+            self.render_into(out, note);
+        }
+    }
+
+    fn source_line(&mut self, file: &str, line: u32) -> Option<String> {
+        let key = normalize_path(file);
+        if !self.sources.contains_key(&key) {
+            let text = self
+                .source_provider
+                .as_mut()
+                .and_then(|source_provider| source_provider(file));
+            self.sources.insert(key.clone(), text);
+        }
+
+        self.sources[&key]
+            .as_deref()?
+            .lines()
+            .nth(line.checked_sub(1)? as usize)
+            .map(|line| line.trim_end_matches('\r').to_owned())
+    }
+}
+
+/// Returns the whitespace preceding the 1-based byte `column` of `line`, keeping tabs so that
+/// the underline lines up, and the length of the underline.
+///
+/// The length is taken from the `^~~~` range DXC printed in `snippet` if there is one, and
+/// otherwise spans the identifier or number at `column`.
+fn underline(line: &str, column: u32, snippet: &Option<String>) -> (String, usize) {
+    let start = (column.saturating_sub(1) as usize).min(line.len());
+    let start = (0..=start)
+        .rev()
+        .find(|&i| line.is_char_boundary(i))
+        .unwrap_or(0);
+
+    let indent = line[..start]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    let from_snippet = snippet.as_deref().and_then(|snippet| {
+        snippet
+            .lines()
+            .map(str::trim)
+            .find(|l| l.starts_with('^') && l.chars().all(|c| c == '^' || c == '~'))
+            .map(str::len)
+    });
+    let token_len = line[start..]
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .count();
+
+    (indent, from_snippet.unwrap_or(token_len).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::include::VirtualIncludeHandler;
+
+    fn include(file: &str, line: u32) -> IncludeLocation {
+        IncludeLocation {
+            file: file.to_owned(),
+            line,
+        }
+    }
+
+    #[test]
+    fn renders_snippets_through_include_handler() {
+        let diagnostics = parse_diagnostics(
+            "In file included from shader.hlsl:1:
+./common.hlsli:2:5: error: use of undeclared identifier 'foo'",
+        );
+        let include_handler =
+            VirtualIncludeHandler::new().with_file("common.hlsli", "void f() {\n    foo();\n}\n");
+
+        let rendered = DiagnosticRenderer::new()
+            .with_include_handler(&include_handler)
+            .render_all(&diagnostics);
+        assert_eq!(
+            rendered,
+            "error: use of undeclared identifier 'foo'
+ --> ./common.hlsli:2:5
+  |
+2 |     foo();
+  |     ^^^
+  = included from shader.hlsl:1
+"
+        );
+
+        // Without a handler only the location is shown
+        let rendered = DiagnosticRenderer::new().render_all(&diagnostics);
+        assert!(!rendered.contains("foo();"));
+    }
+
+    #[test]
+    fn include_stack_carries_over_within_a_header() {
+        let messages = "\
+In file included from shader.hlsl:2:
+In file included from ./lighting.hlsli:5:
+./common.hlsli:7:12: warning: first
+./common.hlsli:9:3: error: second
+shader.hlsl:10:5: error: third
+./common.hlsli:11:1: error: fourth
+In file included from shader.hlsl:3:
+./other.hlsli:1:1: error: fifth
+";
+        let diagnostics = parse_diagnostics(messages);
+        let stacks = diagnostics
+            .iter()
+            .map(|d| d.include_stack.clone())
+            .collect::<Vec<_>>();
+
+        let common = vec![include("shader.hlsl", 2), include("./lighting.hlsli", 5)];
+        assert_eq!(
+            stacks,
+            [
+                common.clone(),
+                common,
+                vec![],
+                // Clang would have printed the chain again after leaving the header
+                vec![],
+                vec![include("shader.hlsl", 3)],
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_locations_severities_and_options() {
+        let diagnostics = parse_diagnostics(
+            "C:\\shaders\\a.hlsl:3:4: fatal error: 'missing.hlsli' file not found\n\
+             a.hlsl:7: warning: no column [-Wunused-value]\n\
+             error: unknown argument: '-foo'\n",
+        );
+        assert_eq!(diagnostics.len(), 3);
+
+        assert_eq!(diagnostics[0].file.as_deref(), Some("C:\\shaders\\a.hlsl"));
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(3), Some(4))
+        );
+        assert_eq!(diagnostics[0].severity, Severity::Fatal);
+        assert_eq!(diagnostics[0].message, "'missing.hlsli' file not found");
+
+        assert_eq!(
+            (diagnostics[1].line, diagnostics[1].column),
+            (Some(7), None)
+        );
+        assert_eq!(diagnostics[1].option.as_deref(), Some("-Wunused-value"));
+        assert_eq!(diagnostics[1].message, "no column");
+
+        assert_eq!(diagnostics[2].file, None);
+        assert_eq!(diagnostics[2].severity, Severity::Error);
+        assert_eq!(diagnostics[2].message, "unknown argument: '-foo'");
+    }
+
+    #[test]
+    fn attaches_notes_and_snippets() {
+        let diagnostics = parse_diagnostics(
+            "a.hlsl:1:1: error: redefinition of 'x'\n\
+             int x;\n\
+             ^\n\
+             a.hlsl:0:1: note: previous definition is here\n\
+             int x;\n\
+             \n\
+             a.hlsl:2:1: warning: unused\n",
+        );
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].snippet.as_deref(), Some("int x;\n^"));
+        assert_eq!(diagnostics[0].notes.len(), 1);
+        assert_eq!(diagnostics[0].notes[0].snippet.as_deref(), Some("int x;"));
+        assert_eq!(diagnostics[1].snippet, None);
+    }
+}
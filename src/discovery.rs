@@ -0,0 +1,488 @@
+//! Locating the `dxcompiler` and `dxil` shared libraries on disk.
+//!
+//! [`Dxc::new(None)`](crate::Dxc::new) and [`Dxil::new(None)`](crate::Dxil::new) try the
+//! candidates returned by [`LibrarySearch::candidates()`] in order and load the first one that
+//! succeeds.  When none of them load, [`HassleError::LibraryNotFound`] lists every path that was
+//! tried together with the reason it failed.  A library that is named by its own environment
+//! variable must load: the search stops with an error instead of silently picking up another
+//! library.
+
+use crate::utils::{HassleError, Result};
+use libloading::{library_filename, Library};
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Where a candidate library path came from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LibraryOrigin {
+    /// Read from the named environment variable.
+    EnvVar(&'static str),
+    /// The bare library file name, resolved by the platform loader through `PATH`,
+    /// `LD_LIBRARY_PATH` and friends.
+    LoaderSearchPath,
+    /// The `bin` (Windows) or `lib` directory of the Vulkan SDK pointed to by `VULKAN_SDK`.
+    VulkanSdk,
+    /// The directory containing the current executable.
+    ExecutableDir,
+    /// A common installation prefix for the current platform.
+    InstallPrefix,
+}
+
+impl fmt::Display for LibraryOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EnvVar(name) => write!(f, "${}", name),
+            Self::LoaderSearchPath => f.write_str("loader search path"),
+            Self::VulkanSdk => f.write_str("$VULKAN_SDK"),
+            Self::ExecutableDir => f.write_str("executable directory"),
+            Self::InstallPrefix => f.write_str("install prefix"),
+        }
+    }
+}
+
+/// A path that was tried while looking for a library, and why it could not be loaded.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LoadAttempt {
+    pub path: PathBuf,
+    pub origin: LibraryOrigin,
+    pub error: String,
+}
+
+impl fmt::Display for LoadAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}): {}",
+            self.path.display(),
+            self.origin,
+            self.error
+        )
+    }
+}
+
+/// Formats the attempts of [`HassleError::LibraryNotFound`], one per line.
+pub(crate) fn format_attempts(attempts: &[LoadAttempt]) -> String {
+    attempts
+        .iter()
+        .map(|attempt| format!("\n  {}", attempt))
+        .collect()
+}
+
+/// Describes how to find one of the DXC libraries.
+///
+/// ```
+/// use hassle_rs::discovery::LibrarySearch;
+///
+/// for (path, origin) in LibrarySearch::dxcompiler().candidates() {
+///     println!("{} ({})", path.display(), origin);
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LibrarySearch {
+    name: &'static str,
+    /// Variables naming this library or its directory.
+    env_vars: &'static [&'static str],
+    /// Variables naming another DXC library or its directory, which this library is installed
+    /// next to.
+    sibling_env_vars: &'static [&'static str],
+}
+
+impl LibrarySearch {
+    /// Searches for `dxcompiler`, honouring `HASSLE_DXC_PATH` and `DXC_PATH`.
+    pub fn dxcompiler() -> Self {
+        Self {
+            name: "dxcompiler",
+            env_vars: &["HASSLE_DXC_PATH", "DXC_PATH"],
+            sibling_env_vars: &[],
+        }
+    }
+
+    /// Searches for `dxil`, honouring `HASSLE_DXIL_PATH`.  `HASSLE_DXC_PATH` and `DXC_PATH`
+    /// are honoured as well, as both libraries ship in the same directory.
+    pub fn dxil() -> Self {
+        Self {
+            name: "dxil",
+            env_vars: &["HASSLE_DXIL_PATH"],
+            sibling_env_vars: &["HASSLE_DXC_PATH", "DXC_PATH"],
+        }
+    }
+
+    /// The platform-specific file name of the library, such as `libdxcompiler.so`.
+    pub fn file_name(&self) -> OsString {
+        library_filename(self.name)
+    }
+
+    /// Turns a path to either the library itself or a directory containing it into a path to
+    /// the library.
+    pub fn resolve(&self, path: impl Into<PathBuf>) -> PathBuf {
+        let path = path.into();
+        if path.is_dir() {
+            path.join(self.file_name())
+        } else {
+            path
+        }
+    }
+
+    /// Every path this search tries, in order:
+    ///
+    /// 1. The environment variables, which may name the library or its directory.  A file named
+    ///    by the variable of another DXC library, such as `DXC_PATH` when searching for `dxil`,
+    ///    is replaced by this library in the same directory;
+    /// 2. The bare file name, which the platform loader resolves through its own search path;
+    /// 3. The Vulkan SDK, when `VULKAN_SDK` is set;
+    /// 4. The directory containing the current executable;
+    /// 5. Common installation prefixes, such as `/usr/lib` or the Windows SDK.
+    pub fn candidates(&self) -> Vec<(PathBuf, LibraryOrigin)> {
+        let mut candidates = vec![];
+
+        for &var in self.env_vars {
+            if let Some(value) = std::env::var_os(var).filter(|value| !value.is_empty()) {
+                candidates.push((self.resolve(value), LibraryOrigin::EnvVar(var)));
+            }
+        }
+
+        for &var in self.sibling_env_vars {
+            if let Some(value) = std::env::var_os(var).filter(|value| !value.is_empty()) {
+                let path = PathBuf::from(value);
+                let path = if path.is_file() {
+                    path.with_file_name(self.file_name())
+                } else {
+                    self.resolve(path)
+                };
+                candidates.push((path, LibraryOrigin::EnvVar(var)));
+            }
+        }
+
+        candidates.push((
+            PathBuf::from(self.file_name()),
+            LibraryOrigin::LoaderSearchPath,
+        ));
+
+        if let Some(sdk) = std::env::var_os("VULKAN_SDK").filter(|sdk| !sdk.is_empty()) {
+            let dir = if cfg!(windows) { "Bin" } else { "lib" };
+            candidates.push((
+                Path::new(&sdk).join(dir).join(self.file_name()),
+                LibraryOrigin::VulkanSdk,
+            ));
+        }
+
+        if let Some(dir) = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_owned))
+        {
+            candidates.push((dir.join(self.file_name()), LibraryOrigin::ExecutableDir));
+        }
+
+        for prefix in install_prefixes() {
+            candidates.push((prefix.join(self.file_name()), LibraryOrigin::InstallPrefix));
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        candidates.retain(|(path, _)| seen.insert(path.clone()));
+        candidates
+    }
+
+    /// Loads the library from `path` if given, otherwise from the first of
+    /// [`Self::candidates()`] that loads.  Returns the library with the path it was loaded from.
+    pub(crate) fn load(&self, path: Option<PathBuf>) -> Result<(Library, PathBuf)> {
+        if let Some(path) = path {
+            let path = self.resolve(path);
+            return match unsafe { Library::new(&path) } {
+                Ok(library) => Ok((library, path)),
+                Err(inner) => Err(HassleError::LoadLibraryError {
+                    filename: path,
+                    inner,
+                }),
+            };
+        }
+
+        let mut attempts = vec![];
+        for (path, origin) in self.candidates() {
+            // Don't bother the loader with absolute paths that do not exist, its error for those
+            // is less readable
+            let error = if path.is_absolute() && !path.exists() {
+                "file does not exist".to_owned()
+            } else {
+                match unsafe { Library::new(&path) } {
+                    Ok(library) => return Ok((library, path)),
+                    Err(e) => e.to_string(),
+                }
+            };
+
+            let explicit =
+                matches!(origin, LibraryOrigin::EnvVar(var) if self.env_vars.contains(&var));
+            attempts.push(LoadAttempt {
+                path,
+                origin,
+                error,
+            });
+            if explicit {
+                break;
+            }
+        }
+
+        Err(HassleError::LibraryNotFound {
+            name: self.file_name().to_string_lossy().into_owned(),
+            attempts,
+        })
+    }
+}
+
+#[cfg(windows)]
+fn install_prefixes() -> Vec<PathBuf> {
+    let arch = if cfg!(target_arch = "aarch64") {
+        "arm64"
+    } else if cfg!(target_arch = "x86") {
+        "x86"
+    } else {
+        "x64"
+    };
+
+    let mut prefixes = vec![];
+    for var in ["ProgramFiles(x86)", "ProgramFiles"] {
+        let program_files = match std::env::var_os(var) {
+            Some(program_files) => program_files,
+            None => continue,
+        };
+        let bin = Path::new(&program_files).join(r"Windows Kits\10\bin");
+
+        // The SDK installs into versioned directories, prefer the newest
+        let mut versions = std::fs::read_dir(&bin)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .map_or(false, |name| name.starts_with("10."))
+            })
+            .collect::<Vec<_>>();
+        versions.sort();
+        prefixes.extend(versions.into_iter().rev().map(|version| version.join(arch)));
+        prefixes.push(bin.join(arch));
+    }
+    prefixes
+}
+
+#[cfg(target_os = "macos")]
+fn install_prefixes() -> Vec<PathBuf> {
+    ["/usr/local/lib", "/opt/homebrew/lib"]
+        .iter()
+        .map(PathBuf::from)
+        .collect()
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn install_prefixes() -> Vec<PathBuf> {
+    [
+        "/usr/local/lib",
+        "/usr/lib",
+        "/usr/lib64",
+        "/usr/lib/x86_64-linux-gnu",
+        "/usr/lib/aarch64-linux-gnu",
+        "/opt/dxc/lib",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    const VARS: &[&str] = &[
+        "HASSLE_DXC_PATH",
+        "DXC_PATH",
+        "HASSLE_DXIL_PATH",
+        "VULKAN_SDK",
+    ];
+
+    /// Runs `f` with only the given search variables set.
+    fn with_env<R>(vars: &[(&str, &OsStr)], f: impl FnOnce() -> R) -> R {
+        let saved = VARS
+            .iter()
+            .map(|&var| (var, std::env::var_os(var)))
+            .collect::<Vec<_>>();
+        for &var in VARS {
+            std::env::remove_var(var);
+        }
+        for (var, value) in vars {
+            std::env::set_var(var, value);
+        }
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+
+        for (var, value) in saved {
+            match value {
+                Some(value) => std::env::set_var(var, value),
+                None => std::env::remove_var(var),
+            }
+        }
+        result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("hassle-discovery-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // The environment is shared by all tests, so everything that sets the search variables runs
+    // sequentially from a single test
+    #[test]
+    fn env_var_search() {
+        candidates_are_ordered();
+        candidates_are_deduplicated();
+        files_map_to_the_searched_library();
+        explicit_env_var_stops_the_search();
+    }
+
+    fn candidates_are_ordered() {
+        let dir = temp_dir("ordered");
+        let (hassle_dir, dxc_dir) = (dir.join("hassle"), dir.join("dxc"));
+        std::fs::create_dir_all(&hassle_dir).unwrap();
+        std::fs::create_dir_all(&dxc_dir).unwrap();
+
+        let search = LibrarySearch::dxcompiler();
+        let candidates = with_env(
+            &[
+                ("HASSLE_DXC_PATH", hassle_dir.as_os_str()),
+                ("DXC_PATH", dxc_dir.as_os_str()),
+                ("VULKAN_SDK", dir.join("sdk").as_os_str()),
+            ],
+            || search.candidates(),
+        );
+
+        let sdk_dir = if cfg!(windows) { "Bin" } else { "lib" };
+        assert_eq!(
+            candidates[..4],
+            [
+                (
+                    hassle_dir.join(search.file_name()),
+                    LibraryOrigin::EnvVar("HASSLE_DXC_PATH")
+                ),
+                (
+                    dxc_dir.join(search.file_name()),
+                    LibraryOrigin::EnvVar("DXC_PATH")
+                ),
+                (
+                    PathBuf::from(search.file_name()),
+                    LibraryOrigin::LoaderSearchPath
+                ),
+                (
+                    dir.join("sdk").join(sdk_dir).join(search.file_name()),
+                    LibraryOrigin::VulkanSdk
+                ),
+            ]
+        );
+        assert_eq!(candidates[4].1, LibraryOrigin::ExecutableDir);
+        assert!(candidates[5..]
+            .iter()
+            .all(|(_, origin)| *origin == LibraryOrigin::InstallPrefix));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn candidates_are_deduplicated() {
+        let dir = temp_dir("dedup");
+
+        let search = LibrarySearch::dxcompiler();
+        let candidates = with_env(
+            &[
+                ("HASSLE_DXC_PATH", dir.as_os_str()),
+                ("DXC_PATH", dir.as_os_str()),
+            ],
+            || search.candidates(),
+        );
+
+        assert_eq!(
+            candidates[..2],
+            [
+                (
+                    dir.join(search.file_name()),
+                    LibraryOrigin::EnvVar("HASSLE_DXC_PATH")
+                ),
+                (
+                    PathBuf::from(search.file_name()),
+                    LibraryOrigin::LoaderSearchPath
+                ),
+            ]
+        );
+        let mut paths = candidates.iter().map(|(path, _)| path).collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), candidates.len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn files_map_to_the_searched_library() {
+        let dir = temp_dir("files");
+        // An exact file, which need not follow the platform naming
+        let dxcompiler = dir.join("libdxcompiler.so.3.7");
+        std::fs::write(&dxcompiler, b"").unwrap();
+
+        let (dxc_candidates, dxil_candidates) =
+            with_env(&[("DXC_PATH", dxcompiler.as_os_str())], || {
+                (
+                    LibrarySearch::dxcompiler().candidates(),
+                    LibrarySearch::dxil().candidates(),
+                )
+            });
+
+        // Honoured as-is by the library it names
+        assert_eq!(
+            dxc_candidates[0],
+            (dxcompiler.clone(), LibraryOrigin::EnvVar("DXC_PATH"))
+        );
+        // And mapped to its sibling in the same directory
+        assert_eq!(
+            dxil_candidates[0],
+            (
+                dir.join(library_filename("dxil")),
+                LibraryOrigin::EnvVar("DXC_PATH")
+            )
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn explicit_env_var_stops_the_search() {
+        let missing = temp_dir("explicit")
+            .join("missing")
+            .join("libdxcompiler.so");
+
+        for var in ["HASSLE_DXC_PATH", "DXC_PATH"] {
+            let result = with_env(&[(var, missing.as_os_str())], || {
+                LibrarySearch::dxcompiler().load(None)
+            });
+            match result {
+                Err(HassleError::LibraryNotFound { attempts, .. }) => assert_eq!(
+                    attempts,
+                    [LoadAttempt {
+                        path: missing.clone(),
+                        origin: LibraryOrigin::EnvVar(var),
+                        error: "file does not exist".to_owned(),
+                    }]
+                ),
+                result => panic!("unexpected result for ${}: {:?}", var, result.map(|_| ())),
+            }
+        }
+
+        // `DXC_PATH` only hints at where `dxil` lives, so the search continues
+        let result = with_env(&[("DXC_PATH", missing.as_os_str())], || {
+            LibrarySearch::dxil().load(None)
+        });
+        if let Err(HassleError::LibraryNotFound { attempts, .. }) = result {
+            assert_eq!(attempts[0].origin, LibraryOrigin::EnvVar("DXC_PATH"));
+            assert!(attempts.len() > 1);
+        }
+
+        std::fs::remove_dir_all(temp_dir("explicit")).unwrap();
+    }
+}
@@ -40,6 +40,12 @@
 // IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+const PADDING: [u8; 64] = [
+    0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0,
+];
+
 #[derive(Clone)]
 pub struct Context {
     buffer: [u8; 64],
@@ -57,6 +63,33 @@ impl Context {
         }
     }
 
+    /// Finalize and return the digest.
+    // hassle-rs modification: reinstated for hashing outside of DXIL signing
+    pub fn compute(mut self) -> [u8; 16] {
+        let mut input = [0u32; 16];
+        let k = ((self.count[0] >> 3) & 0x3f) as usize;
+        input[14] = self.count[0];
+        input[15] = self.count[1];
+        consume(
+            &mut self,
+            &PADDING[..(if k < 56 { 56 - k } else { 120 - k })],
+        );
+        let mut j = 0;
+        for v in input.iter_mut().take(14) {
+            *v = ((self.buffer[j + 3] as u32) << 24)
+                | ((self.buffer[j + 2] as u32) << 16)
+                | ((self.buffer[j + 1] as u32) << 8)
+                | (self.buffer[j] as u32);
+            j += 4;
+        }
+        transform(&mut self.state, &input);
+        let mut digest = [0u8; 16];
+        for (bytes, state) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&state.to_le_bytes());
+        }
+        digest
+    }
+
     /// Consume data.
     #[cfg(target_pointer_width = "32")]
     #[inline]
@@ -1,7 +1,7 @@
 #![allow(clippy::transmute_ptr_to_ptr)]
 #![allow(clippy::too_many_arguments)]
 
-use crate::os::{HRESULT, LPCWSTR, LPWSTR};
+use crate::os::{BSTR, HRESULT, LPCSTR, LPCWSTR, LPWSTR};
 use com::{interfaces, interfaces::IUnknown, IID};
 use std::ffi::c_void;
 
@@ -10,20 +10,78 @@ use std::ffi::c_void;
 pub const CP_ACP: u32 = 0;
 /// Unicode (UTF-8)
 pub const CP_UTF8: u32 = 65001;
+/// Unicode (UTF-16), little endian
+pub const CP_UTF16: u32 = 1200;
+/// Unicode (UTF-16), big endian
+pub const CP_UTF16BE: u32 = 1201;
+/// Unicode (UTF-32), little endian
+pub const CP_UTF32: u32 = 12000;
+/// Unicode (UTF-32), big endian
+pub const CP_UTF32BE: u32 = 12001;
+/// Encoding of DXC's "wide" strings, which follow the size of `wchar_t`: UTF-16 on Windows and
+/// UTF-32 elsewhere
+pub const CP_WIDE: u32 = if cfg!(windows) { CP_UTF16 } else { CP_UTF32 };
 
 pub type DxcCreateInstanceProc<T> =
     extern "system" fn(rclsid: &IID, riid: &IID, ppv: *mut Option<T>) -> HRESULT;
 
-pub type DxcCreateInstanceProc2 = extern "system" fn(
+pub type DxcCreateInstanceProc2<T> = extern "system" fn(
     malloc: /* IMalloc */ *const c_void,
     rclsid: &IID,
     riid: &IID,
-    ppv: *mut *mut c_void,
+    ppv: *mut Option<T>,
 ) -> HRESULT;
 
+pub const STREAM_SEEK_SET: u32 = 0;
+pub const STREAM_SEEK_CUR: u32 = 1;
+pub const STREAM_SEEK_END: u32 = 2;
+
 pub const DFCC_DXIL: u32 = u32::from_le_bytes([b'D', b'X', b'I', b'L']);
 
 interfaces! {
+    #[uuid("00000002-0000-0000-C000-000000000046")]
+    pub(crate) unsafe interface IMalloc: IUnknown {
+        pub(crate) fn alloc(&self, cb: usize) -> *mut c_void;
+        pub(crate) fn realloc(&self, pv: *mut c_void, cb: usize) -> *mut c_void;
+        pub(crate) fn free(&self, pv: *mut c_void);
+        pub(crate) fn get_size(&self, pv: *mut c_void) -> usize;
+        pub(crate) fn did_alloc(&self, pv: *mut c_void) -> i32;
+        pub(crate) fn heap_minimize(&self);
+    }
+
+    #[uuid("0c733a30-2a1c-11ce-ade5-00aa0044773d")]
+    pub(crate) unsafe interface ISequentialStream: IUnknown {
+        pub(crate) fn read(&self, pv: *mut c_void, cb: u32, pcb_read: *mut u32) -> HRESULT;
+        pub(crate) fn write(&self, pv: *const c_void, cb: u32, pcb_written: *mut u32) -> HRESULT;
+    }
+
+    #[uuid("0000000c-0000-0000-C000-000000000046")]
+    pub(crate) unsafe interface IStream: ISequentialStream {
+        pub(crate) fn seek(
+            &self,
+            dlib_move: i64,
+            dw_origin: u32,
+            plib_new_position: *mut u64,
+        ) -> HRESULT;
+        pub(crate) fn set_size(&self, lib_new_size: u64) -> HRESULT;
+        pub(crate) fn copy_to(
+            &self,
+            pstm: IStream,
+            cb: u64,
+            pcb_read: *mut u64,
+            pcb_written: *mut u64,
+        ) -> HRESULT;
+        pub(crate) fn commit(&self, grf_commit_flags: u32) -> HRESULT;
+        pub(crate) fn revert(&self) -> HRESULT;
+        pub(crate) fn lock_region(&self, lib_offset: u64, cb: u64, dw_lock_type: u32) -> HRESULT;
+        pub(crate) fn unlock_region(&self, lib_offset: u64, cb: u64, dw_lock_type: u32)
+            -> HRESULT;
+        pub(crate) fn stat(&self, pstatstg: *mut /* STATSTG */ c_void, grf_stat_flag: u32)
+            -> HRESULT;
+        // `Clone`, renamed so that it does not shadow `Clone::clone()`
+        pub(crate) fn clone_stream(&self, ppstm: *mut Option<IStream>) -> HRESULT;
+    }
+
     #[uuid("8ba5fb08-5195-40e2-ac58-0d989c3a0102")]
     pub(crate) unsafe interface IDxcBlob: IUnknown {
         pub(crate) fn get_buffer_pointer(&self) -> *mut c_void;
@@ -37,7 +95,7 @@ interfaces! {
 
     #[uuid("e5204dc7-d18c-4c3c-bdfb-851673980fe7")]
     pub(crate) unsafe interface IDxcLibrary: IUnknown {
-        pub(crate) fn set_malloc(&self, malloc: *const c_void) -> HRESULT;
+        pub(crate) fn set_malloc(&self, malloc: IMalloc) -> HRESULT;
         pub(crate) fn create_blob_from_blob(
             &self,
             blob: IDxcBlob,
@@ -68,7 +126,7 @@ interfaces! {
         pub(crate) fn create_blob_with_encoding_on_malloc(
             &self,
             text: *const c_void,
-            malloc: *const /* IMalloc */ c_void,
+            malloc: IMalloc,
             size: u32,
             code_page: u32,
             blob_encoding: *mut Option<IDxcBlobEncoding>,
@@ -80,7 +138,7 @@ interfaces! {
         pub(crate) fn create_stream_from_blob_read_only(
             &self,
             blob: IDxcBlob,
-            stream: *mut *mut /* IStream */ c_void,
+            stream: *mut Option<IStream>,
         ) -> HRESULT;
         pub(crate) fn get_blob_as_utf8(
             &self,
@@ -117,6 +175,79 @@ pub struct DxcDefine {
     pub value: LPCWSTR,
 }
 
+/// `D3D12_SHADER_DESC`, filled in by `ID3D12ShaderReflection::GetDesc()`.
+#[repr(C)]
+pub struct D3D12ShaderDesc {
+    pub version: u32,
+    pub creator: LPCSTR,
+    pub flags: u32,
+    pub constant_buffers: u32,
+    pub bound_resources: u32,
+    pub input_parameters: u32,
+    pub output_parameters: u32,
+    pub instruction_count: u32,
+    pub temp_register_count: u32,
+    pub temp_array_count: u32,
+    pub def_count: u32,
+    pub dcl_count: u32,
+    pub texture_normal_instructions: u32,
+    pub texture_load_instructions: u32,
+    pub texture_comp_instructions: u32,
+    pub texture_bias_instructions: u32,
+    pub texture_gradient_instructions: u32,
+    pub float_instruction_count: u32,
+    pub int_instruction_count: u32,
+    pub uint_instruction_count: u32,
+    pub static_flow_control_count: u32,
+    pub dynamic_flow_control_count: u32,
+    pub macro_instruction_count: u32,
+    pub array_instruction_count: u32,
+    pub cut_instruction_count: u32,
+    pub emit_instruction_count: u32,
+    pub gs_output_topology: u32,
+    pub gs_max_output_vertex_count: u32,
+    pub input_primitive: u32,
+    pub patch_constant_parameters: u32,
+    pub gs_instance_count: u32,
+    pub control_points: u32,
+    pub hs_output_primitive: u32,
+    pub hs_partitioning: u32,
+    pub tessellator_domain: u32,
+    pub barrier_instructions: u32,
+    pub interlocked_instructions: u32,
+    pub texture_store_instructions: u32,
+}
+
+/// `D3D12_SHADER_INPUT_BIND_DESC`, filled in by `ID3D12ShaderReflection::GetResourceBindingDesc()`.
+#[repr(C)]
+pub struct D3D12ShaderInputBindDesc {
+    pub name: LPCSTR,
+    pub input_type: u32,
+    pub bind_point: u32,
+    pub bind_count: u32,
+    pub flags: u32,
+    pub return_type: u32,
+    pub dimension: u32,
+    pub num_samples: u32,
+    pub space: u32,
+    pub id: u32,
+}
+
+/// `D3D12_SIGNATURE_PARAMETER_DESC`, filled in by `ID3D12ShaderReflection::GetInputParameterDesc()`
+/// and `GetOutputParameterDesc()`.
+#[repr(C)]
+pub struct D3D12SignatureParameterDesc {
+    pub semantic_name: LPCSTR,
+    pub semantic_index: u32,
+    pub register: u32,
+    pub system_value_type: u32,
+    pub component_type: u32,
+    pub mask: u8,
+    pub read_write_mask: u8,
+    pub stream: u32,
+    pub min_precision: u32,
+}
+
 interfaces! {
     #[uuid("8c210bf3-011f-4422-8d70-6f9acb8db617")]
     pub(crate) unsafe interface IDxcCompiler: IUnknown {
@@ -333,13 +464,60 @@ interfaces! {
     }
 
     #[uuid("fb6904c4-42f0-4b62-9c46-983af7da7c83")]
-    pub(crate) unsafe interface IDxcVersionInfo2: IUnknown {
+    pub(crate) unsafe interface IDxcVersionInfo2: IDxcVersionInfo {
         pub(crate) fn get_commit_info(
             &self,
             commit_count: *mut u32,
             commit_hash: *mut *mut u8,
         ) -> HRESULT;
     }
+
+    #[uuid("e6c9647e-9d6a-4c3b-b94c-524b5a6c343d")]
+    pub(crate) unsafe interface IDxcPdbUtils: IUnknown {
+        pub(crate) fn load(&self, pdb_or_dxil: IDxcBlob) -> HRESULT;
+        pub(crate) fn get_source_count(&self, count: *mut u32) -> HRESULT;
+        pub(crate) fn get_source(
+            &self,
+            index: u32,
+            result: *mut Option<IDxcBlobEncoding>,
+        ) -> HRESULT;
+        pub(crate) fn get_source_name(&self, index: u32, result: *mut BSTR) -> HRESULT;
+        pub(crate) fn get_flag_count(&self, count: *mut u32) -> HRESULT;
+        pub(crate) fn get_flag(&self, index: u32, result: *mut BSTR) -> HRESULT;
+        pub(crate) fn get_arg_count(&self, count: *mut u32) -> HRESULT;
+        pub(crate) fn get_arg(&self, index: u32, result: *mut BSTR) -> HRESULT;
+        pub(crate) fn get_arg_pair_count(&self, count: *mut u32) -> HRESULT;
+        pub(crate) fn get_arg_pair(
+            &self,
+            index: u32,
+            name: *mut BSTR,
+            value: *mut BSTR,
+        ) -> HRESULT;
+        pub(crate) fn get_define_count(&self, count: *mut u32) -> HRESULT;
+        pub(crate) fn get_define(&self, index: u32, result: *mut BSTR) -> HRESULT;
+        pub(crate) fn get_target_profile(&self, result: *mut BSTR) -> HRESULT;
+        pub(crate) fn get_entry_point(&self, result: *mut BSTR) -> HRESULT;
+        pub(crate) fn get_main_file_name(&self, result: *mut BSTR) -> HRESULT;
+        pub(crate) fn get_hash(&self, result: *mut Option<IDxcBlob>) -> HRESULT;
+        pub(crate) fn get_name(&self, result: *mut BSTR) -> HRESULT;
+        pub(crate) fn is_full_pdb(&self) -> i32;
+        pub(crate) fn get_full_pdb(&self, full_pdb: *mut Option<IDxcBlob>) -> HRESULT;
+        pub(crate) fn get_version_info(
+            &self,
+            version_info: *mut Option<IDxcVersionInfo>,
+        ) -> HRESULT;
+        pub(crate) fn set_compiler(&self, compiler: *const /* IDxcCompiler3 */ c_void) -> HRESULT;
+        pub(crate) fn compile_for_full_pdb(
+            &self,
+            result: *mut *mut /* IDxcResult */ c_void,
+        ) -> HRESULT;
+        pub(crate) fn override_args(
+            &self,
+            arg_pairs: *const /* DxcArgPair */ c_void,
+            num_arg_pairs: u32,
+        ) -> HRESULT;
+        pub(crate) fn override_root_signature(&self, root_signature: LPCWSTR) -> HRESULT;
+    }
 }
 
 pub const CLSID_DxcCompiler: IID = IID {
@@ -396,3 +574,9 @@ pub const CLSID_DxcContainerBuilder: IID = IID {
     data3: 0x4574,
     data4: [0xb4, 0xd0, 0x87, 0x41, 0xe2, 0x52, 0x40, 0xd2],
 };
+pub const CLSID_DxcPdbUtils: IID = IID {
+    data1: 0x54621dfb,
+    data2: 0xf2ce,
+    data3: 0x457e,
+    data4: [0xae, 0x8c, 0xec, 0x35, 0x5f, 0xae, 0xec, 0x7c],
+};
@@ -0,0 +1,560 @@
+//! Reusable [`IncludeHandler`] and [`DxcIncludeHandler`] implementations.
+
+use crate::wrapper::{DxcIncludeHandler, IncludeError, IncludeHandler, IncludeRequest};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Normalizes an include path into a canonical, `/`-separated form so that the different
+/// spellings DXC produces for the same file (`./foo.hlsli`, `.\foo.hlsli`, `a/../foo.hlsli`)
+/// all map onto the same key.
+///
+/// This is a purely lexical operation; the filesystem is never consulted.
+///
+/// ```
+/// use hassle_rs::include::normalize_path;
+///
+/// assert_eq!(normalize_path("./shaders\\common/../lighting.hlsli"), "shaders/lighting.hlsli");
+/// assert_eq!(normalize_path("/abs//path/./file.hlsl"), "/abs/path/file.hlsl");
+/// assert_eq!(normalize_path("../outside.hlsli"), "../outside.hlsli");
+/// ```
+pub fn normalize_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let is_absolute = path.starts_with('/');
+
+    let mut components: Vec<&str> = vec![];
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => match components.last() {
+                Some(&last) if last != ".." => {
+                    components.pop();
+                }
+                // Cannot go above the root of an absolute path
+                _ if is_absolute => {}
+                _ => components.push(".."),
+            },
+            c => components.push(c),
+        }
+    }
+
+    let normalized = components.join("/");
+    if is_absolute {
+        format!("/{}", normalized)
+    } else {
+        normalized
+    }
+}
+
+/// Serves includes from an in-memory map of path to file contents, making it possible to
+/// compile shaders without touching the filesystem (for example shader libraries embedded with
+/// [`include_str!`] or files extracted from an archive).
+///
+/// Paths are [normalized](normalize_path) both when they are inserted and when DXC looks them
+/// up, so `"common/math.hlsli"` satisfies `#include "./common/math.hlsli"`.
+///
+/// ```
+/// use hassle_rs::{DxcIncludeHandler, VirtualIncludeHandler};
+///
+/// let mut handler = VirtualIncludeHandler::new()
+///     .with_file("common/math.hlsli", "#define PI 3.14159265");
+///
+/// assert!(handler.load_source("./common/math.hlsli".to_owned()).is_some());
+/// assert!(handler.load_source("common/missing.hlsli".to_owned()).is_none());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct VirtualIncludeHandler {
+    files: HashMap<String, Cow<'static, str>>,
+    filesystem_fallback: bool,
+}
+
+impl VirtualIncludeHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, returning the previous contents if `path` was already present.
+    pub fn insert(
+        &mut self,
+        path: &str,
+        contents: impl Into<Cow<'static, str>>,
+    ) -> Option<Cow<'static, str>> {
+        self.files.insert(normalize_path(path), contents.into())
+    }
+
+    /// Builder-style variant of [`Self::insert()`].
+    pub fn with_file(mut self, path: &str, contents: impl Into<Cow<'static, str>>) -> Self {
+        self.insert(path, contents);
+        self
+    }
+
+    /// When enabled, includes that are not present in memory are read from disk instead of
+    /// failing.  Disabled by default.
+    pub fn with_filesystem_fallback(mut self, filesystem_fallback: bool) -> Self {
+        self.filesystem_fallback = filesystem_fallback;
+        self
+    }
+
+    pub fn remove(&mut self, path: &str) -> Option<Cow<'static, str>> {
+        self.files.remove(&normalize_path(path))
+    }
+
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.files.get(&normalize_path(path)).map(|c| c.as_ref())
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(&normalize_path(path))
+    }
+}
+
+impl<P: AsRef<str>, C: Into<Cow<'static, str>>> std::iter::FromIterator<(P, C)>
+    for VirtualIncludeHandler
+{
+    fn from_iter<I: IntoIterator<Item = (P, C)>>(iter: I) -> Self {
+        let mut handler = Self::new();
+        for (path, contents) in iter {
+            handler.insert(path.as_ref(), contents);
+        }
+        handler
+    }
+}
+
+/// Looks up the contents of a file the way an include handler would serve it, without recording
+/// it as an include.  Used by [`crate::DiagnosticRenderer::with_include_handler()`] to show
+/// snippets from the headers of a compilation after the fact.
+pub trait IncludeSource {
+    /// Returns the contents of `name`, a file name as DXC reports it in diagnostics.
+    fn read_source(&self, name: &str) -> Option<Vec<u8>>;
+}
+
+impl IncludeSource for VirtualIncludeHandler {
+    fn read_source(&self, name: &str) -> Option<Vec<u8>> {
+        if let Some(contents) = self.get(name) {
+            return Some(contents.as_bytes().to_vec());
+        }
+
+        if self.filesystem_fallback {
+            std::fs::read(name).ok()
+        } else {
+            None
+        }
+    }
+}
+
+impl DxcIncludeHandler for VirtualIncludeHandler {
+    fn load_source(&mut self, filename: String) -> Option<String> {
+        if let Some(contents) = self.get(&filename) {
+            return Some(contents.to_owned());
+        }
+
+        if self.filesystem_fallback {
+            std::fs::read_to_string(filename).ok()
+        } else {
+            None
+        }
+    }
+}
+
+/// Describes how [`FileSystemIncludeHandler`] satisfied a single include request.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResolvedInclude {
+    /// Filename as requested by DXC.
+    pub requested: String,
+    /// File that was read from disk.
+    pub path: PathBuf,
+    /// Search directory in which [`Self::path`] was found, or [`None`] if it was resolved
+    /// relative to the including file.
+    pub search_dir: Option<PathBuf>,
+}
+
+/// Reads includes from the filesystem using an ordered list of search directories.
+///
+/// DXC does not tell the handler which file contains the `#include` directive.  Instead it
+/// prefixes the directory of the including file, as _it_ knows it, to the requested name.  This
+/// handler remembers where every virtual directory actually lives on disk, so that a header that
+/// was found in a search directory can include its siblings with `"..."` regardless of how DXC
+/// spelled the path.  A request is resolved by trying, in order:
+///
+/// 1. The real directory of the including file, when it was found in a search directory;
+/// 2. The requested name relative to the base directory, which DXC uses as the root for paths
+///    relative to the main source file;
+/// 3. Every search directory, in the order they were added.
+///
+/// Absolute paths are only ever opened as-is.  Note that DXC does not distinguish between
+/// `#include <...>` and `#include "..."` when calling the include handler.
+///
+/// Every successful lookup is recorded and can be inspected through
+/// [`Self::resolved_includes()`] to find out which directory provided a header.
+#[derive(Clone, Debug)]
+pub struct FileSystemIncludeHandler {
+    base_dir: PathBuf,
+    search_dirs: Vec<PathBuf>,
+    /// Maps the normalized directory DXC believes an include lives in to the directory it was
+    /// actually read from.
+    virtual_dirs: Vec<(String, PathBuf)>,
+    resolved: Vec<ResolvedInclude>,
+}
+
+impl FileSystemIncludeHandler {
+    /// `base_dir` is typically the directory containing the main source file, or the current
+    /// working directory when the main source file name passed to DXC is relative to it.
+    pub fn new(base_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            base_dir: base_dir.into(),
+            search_dirs: vec![],
+            virtual_dirs: vec![],
+            resolved: vec![],
+        }
+    }
+
+    /// Appends a directory to the end of the search list, akin to `-I`.
+    pub fn with_search_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.search_dirs.push(dir.into());
+        self
+    }
+
+    pub fn with_search_dirs<I: IntoIterator<Item = P>, P: Into<PathBuf>>(
+        mut self,
+        dirs: I,
+    ) -> Self {
+        self.search_dirs.extend(dirs.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    pub fn search_dirs(&self) -> &[PathBuf] {
+        &self.search_dirs
+    }
+
+    /// All includes that were successfully resolved so far, in the order DXC requested them.
+    pub fn resolved_includes(&self) -> &[ResolvedInclude] {
+        &self.resolved
+    }
+
+    /// Finds the file that satisfies `filename` without reading it, returning the path together
+    /// with the search directory it was found in (see [`ResolvedInclude::search_dir`]).
+    pub fn resolve(&self, filename: &str) -> Option<(PathBuf, Option<PathBuf>)> {
+        let normalized = normalize_path(filename);
+
+        if Path::new(&normalized).is_absolute() || Path::new(filename).is_absolute() {
+            let path = PathBuf::from(filename);
+            if path.is_file() {
+                return Some((path, None));
+            }
+            return None;
+        }
+
+        let (parent, file_name) = match normalized.rsplit_once('/') {
+            Some((parent, file_name)) => (parent, file_name),
+            None => ("", normalized.as_str()),
+        };
+
+        let includer_relative = self
+            .virtual_dirs
+            .iter()
+            .rev()
+            .filter(|(virtual_dir, _)| virtual_dir == parent)
+            .map(|(_, real_dir)| real_dir.join(file_name))
+            .chain(std::iter::once(self.base_dir.join(&normalized)))
+            .find(|path| path.is_file())
+            .map(|path| (path, None));
+
+        includer_relative.or_else(|| {
+            self.search_dirs.iter().find_map(|dir| {
+                let path = dir.join(&normalized);
+                path.is_file().then(|| (path, Some(dir.clone())))
+            })
+        })
+    }
+}
+
+impl IncludeHandler for FileSystemIncludeHandler {
+    fn load(&mut self, request: &IncludeRequest<'_>) -> Result<Vec<u8>, IncludeError> {
+        let filename = request.name;
+        let (path, search_dir) = self.resolve(filename).ok_or(IncludeError::NotFound)?;
+        let content = std::fs::read(&path)?;
+
+        let normalized = normalize_path(filename);
+        let virtual_dir = normalized
+            .rsplit_once('/')
+            .map_or("", |(parent, _)| parent)
+            .to_owned();
+        if let Some(real_dir) = path.parent() {
+            self.virtual_dirs.push((virtual_dir, real_dir.to_owned()));
+        }
+
+        self.resolved.push(ResolvedInclude {
+            requested: filename.to_owned(),
+            path,
+            search_dir,
+        });
+
+        Ok(content)
+    }
+
+    fn resolved_path(&self, request: &IncludeRequest<'_>) -> Option<PathBuf> {
+        self.resolved
+            .iter()
+            .rev()
+            .find(|resolved| resolved.requested == request.name)
+            .map(|resolved| resolved.path.clone())
+    }
+}
+
+impl IncludeSource for FileSystemIncludeHandler {
+    /// Prefers the file that served an earlier include of `name`, and otherwise resolves it as
+    /// [`Self::load()`](IncludeHandler::load) would.
+    fn read_source(&self, name: &str) -> Option<Vec<u8>> {
+        let path = match self.resolved.iter().rev().find(|r| r.requested == name) {
+            Some(resolved) => resolved.path.clone(),
+            None => self.resolve(name)?.0,
+        };
+        std::fs::read(path).ok()
+    }
+}
+
+/// Files that were read during a compilation, as collected by [`DependencyTracker`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Dependencies {
+    pub main_source: Option<PathBuf>,
+    /// Every include that was successfully loaded, in the order DXC requested them.
+    pub includes: Vec<PathBuf>,
+}
+
+impl Dependencies {
+    /// Returns the main source followed by all includes, without duplicates.
+    pub fn paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = vec![];
+        for path in self.main_source.iter().chain(&self.includes) {
+            if !paths.contains(&path.as_path()) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    /// Formats the dependencies as a Makefile rule for `target`, which is also understood by
+    /// Ninja's `depfile` support.
+    ///
+    /// ```
+    /// use hassle_rs::include::Dependencies;
+    /// use std::path::Path;
+    ///
+    /// let deps = Dependencies {
+    ///     main_source: Some("shaders/copy.hlsl".into()),
+    ///     includes: vec!["shaders/my common.hlsli".into()],
+    /// };
+    ///
+    /// assert_eq!(
+    ///     deps.to_depfile(Path::new("out/copy.dxil")),
+    ///     "out/copy.dxil: \\\n  shaders/copy.hlsl \\\n  shaders/my\\ common.hlsli\n"
+    /// );
+    /// ```
+    pub fn to_depfile(&self, target: &Path) -> String {
+        let mut depfile = format!("{}:", escape_depfile_path(target));
+        for path in self.paths() {
+            depfile.push_str(" \\\n  ");
+            depfile.push_str(&escape_depfile_path(path));
+        }
+        depfile.push('\n');
+        depfile
+    }
+
+    /// Writes [`Self::to_depfile()`] to `depfile`.
+    pub fn write_depfile(&self, depfile: &Path, target: &Path) -> std::io::Result<()> {
+        std::fs::write(depfile, self.to_depfile(target))
+    }
+}
+
+fn escape_depfile_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Decorates another [`IncludeHandler`] and records every include it successfully loads, to
+/// find out which files a compilation depends on.
+///
+/// Includes are recorded by the path the inner handler reports through
+/// [`IncludeHandler::resolved_path()`], such as the file [`FileSystemIncludeHandler`] found in
+/// one of its search directories.  Includes that did not come from a file are not recorded.
+#[derive(Clone, Debug, Default)]
+pub struct DependencyTracker<H> {
+    inner: H,
+    dependencies: Dependencies,
+}
+
+impl<H: IncludeHandler> DependencyTracker<H> {
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            dependencies: Dependencies::default(),
+        }
+    }
+
+    /// Records the main source file as the first dependency.
+    pub fn with_main_source(mut self, main_source: impl Into<PathBuf>) -> Self {
+        self.dependencies.main_source = Some(main_source.into());
+        self
+    }
+
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut H {
+        &mut self.inner
+    }
+
+    pub fn dependencies(&self) -> &Dependencies {
+        &self.dependencies
+    }
+
+    pub fn into_inner(self) -> (H, Dependencies) {
+        (self.inner, self.dependencies)
+    }
+}
+
+impl<H: IncludeHandler> IncludeHandler for DependencyTracker<H> {
+    fn load(&mut self, request: &IncludeRequest<'_>) -> Result<Vec<u8>, IncludeError> {
+        let source = self.inner.load(request)?;
+        if let Some(path) = self.inner.resolved_path(request) {
+            self.dependencies.includes.push(path);
+        }
+        Ok(source)
+    }
+
+    fn resolved_path(&self, request: &IncludeRequest<'_>) -> Option<PathBuf> {
+        self.inner.resolved_path(request)
+    }
+}
+
+impl<H: IncludeSource> IncludeSource for DependencyTracker<H> {
+    fn read_source(&self, name: &str) -> Option<Vec<u8>> {
+        self.inner.read_source(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_path_is_lexical() {
+        assert_eq!(normalize_path("foo.hlsli"), "foo.hlsli");
+        assert_eq!(normalize_path(".\\dir\\foo.hlsli"), "dir/foo.hlsli");
+        assert_eq!(normalize_path("a/b/../../c/./d.hlsli"), "c/d.hlsli");
+        assert_eq!(normalize_path("a/../../b.hlsli"), "../b.hlsli");
+        assert_eq!(normalize_path("../../b.hlsli"), "../../b.hlsli");
+        assert_eq!(normalize_path("/../b.hlsli"), "/b.hlsli");
+        assert_eq!(normalize_path("dir//"), "dir");
+        assert_eq!(normalize_path("."), "");
+    }
+
+    #[test]
+    fn escape_depfile_path_escapes_make_specials() {
+        assert_eq!(
+            escape_depfile_path(Path::new("shaders/plain.hlsl")),
+            "shaders/plain.hlsl"
+        );
+        assert_eq!(
+            escape_depfile_path(Path::new("my shaders/a#b$c.hlsl")),
+            "my\\ shaders/a\\#b$$c.hlsl"
+        );
+    }
+
+    #[test]
+    fn to_depfile_lists_main_source_first_without_duplicates() {
+        let deps = Dependencies {
+            main_source: Some("main.hlsl".into()),
+            includes: vec!["a.hlsli".into(), "main.hlsl".into(), "a.hlsli".into()],
+        };
+        assert_eq!(
+            deps.to_depfile(Path::new("out dir/main.dxil")),
+            "out\\ dir/main.dxil: \\\n  main.hlsl \\\n  a.hlsli\n"
+        );
+    }
+
+    #[test]
+    fn dependency_tracker_records_resolved_path() {
+        let dir = std::env::temp_dir().join(format!("hassle-include-{}", std::process::id()));
+        let search_dir = dir.join("include");
+        std::fs::create_dir_all(&search_dir).unwrap();
+        std::fs::write(search_dir.join("common.hlsli"), "// common").unwrap();
+
+        let mut tracker = DependencyTracker::new(
+            FileSystemIncludeHandler::new(dir.join("shaders")).with_search_dir(&search_dir),
+        );
+        let request = IncludeRequest {
+            name: "./common.hlsli",
+            loaded: &[],
+        };
+        assert_eq!(tracker.load(&request).unwrap(), b"// common");
+        assert!(tracker
+            .load(&IncludeRequest {
+                name: "./missing.hlsli",
+                loaded: &[],
+            })
+            .is_err());
+
+        assert_eq!(
+            tracker.dependencies().includes,
+            vec![search_dir.join("common.hlsli")]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_source_follows_earlier_includes_without_recording() {
+        let dir = std::env::temp_dir().join(format!("hassle-source-{}", std::process::id()));
+        let base_dir = dir.join("shaders");
+        let search_dir = dir.join("include");
+        std::fs::create_dir_all(&base_dir).unwrap();
+        std::fs::create_dir_all(&search_dir).unwrap();
+        std::fs::write(search_dir.join("common.hlsli"), "// common").unwrap();
+
+        let mut tracker = DependencyTracker::new(
+            FileSystemIncludeHandler::new(&base_dir).with_search_dir(&search_dir),
+        );
+        assert_eq!(tracker.read_source("./common.hlsli").unwrap(), b"// common");
+        assert_eq!(tracker.read_source("./missing.hlsli"), None);
+        assert!(tracker.inner().resolved_includes().is_empty());
+        assert!(tracker.dependencies().includes.is_empty());
+
+        let request = IncludeRequest {
+            name: "./common.hlsli",
+            loaded: &[],
+        };
+        tracker.load(&request).unwrap();
+        // A file that now shadows the search directory is not what the compilation read
+        std::fs::write(base_dir.join("common.hlsli"), "// shadow").unwrap();
+        assert_eq!(tracker.read_source("./common.hlsli").unwrap(), b"// common");
+        assert_eq!(tracker.dependencies().includes.len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn virtual_read_source_normalizes_names() {
+        let handler = VirtualIncludeHandler::new().with_file("common/math.hlsli", "// math");
+        assert_eq!(
+            handler.read_source(".\\common\\math.hlsli").unwrap(),
+            b"// math"
+        );
+        assert_eq!(handler.read_source("common/missing.hlsli"), None);
+    }
+}
@@ -1,12 +1,24 @@
-use com::Interface;
-
 use crate::intellisense::ffi::*;
 use crate::os::{CoTaskMemFree, BSTR, LPSTR};
-use crate::utils::Result;
+use crate::utils::{HassleError, Result};
 use crate::wrapper::Dxc;
 use std::ffi::CString;
 use std::mem::ManuallyDrop;
 
+fn to_cstring(string: &str) -> Result<CString> {
+    CString::new(string).map_err(|_| HassleError::InteriorNul(string.to_owned()))
+}
+
+/// Converts and frees a string that DXC allocated with `CoTaskMemAlloc`.
+fn take_lpstr(string: LPSTR) -> Result<String> {
+    if string.is_null() {
+        return Ok(String::new());
+    }
+    let result = crate::utils::from_lpstr(string);
+    unsafe { CoTaskMemFree(string.cast()) };
+    result
+}
+
 pub struct DxcIntellisense {
     inner: IDxcIntelliSense,
 }
@@ -22,15 +34,26 @@ impl DxcIntellisense {
             .result_with_success(options)
     }
 
+    pub fn get_default_diagnostic_display_options(&self) -> Result<DxcDiagnosticDisplayOptions> {
+        let mut options = DxcDiagnosticDisplayOptions::empty();
+        unsafe {
+            self.inner
+                .get_default_diagnostic_display_options(&mut options)
+        }
+        .result_with_success(options)
+    }
+
     pub fn create_index(&self) -> Result<DxcIndex> {
         let mut index = None;
         unsafe { self.inner.create_index(&mut index) }.result()?;
-        Ok(DxcIndex::new(index.unwrap()))
+        Ok(DxcIndex::new(
+            index.ok_or(HassleError::NullOutput("IDxcIndex"))?,
+        ))
     }
 
     pub fn create_unsaved_file(&self, file_name: &str, contents: &str) -> Result<DxcUnsavedFile> {
-        let c_file_name = CString::new(file_name).expect("Failed to convert `file_name`");
-        let c_contents = CString::new(contents).expect("Failed to convert `contents`");
+        let c_file_name = to_cstring(file_name)?;
+        let c_contents = to_cstring(contents)?;
 
         let mut file = None;
         unsafe {
@@ -42,7 +65,9 @@ impl DxcIntellisense {
             )
         }
         .result()?;
-        Ok(DxcUnsavedFile::new(file.unwrap()))
+        Ok(DxcUnsavedFile::new(
+            file.ok_or(HassleError::NullOutput("IDxcUnsavedFile"))?,
+        ))
     }
 }
 
@@ -64,8 +89,7 @@ impl DxcIndex {
         unsaved_files: &[&DxcUnsavedFile],
         options: DxcTranslationUnitFlags,
     ) -> Result<DxcTranslationUnit> {
-        let c_source_filename =
-            CString::new(source_filename).expect("Failed to convert `source_filename`");
+        let c_source_filename = to_cstring(source_filename)?;
 
         let uf = unsaved_files
             .iter()
@@ -76,7 +100,7 @@ impl DxcIndex {
         let mut cliargs = vec![];
 
         for arg in args.iter() {
-            let c_arg = CString::new(*arg).expect("Failed to convert `arg`");
+            let c_arg = to_cstring(arg)?;
             cliargs.push(c_arg.as_ptr().cast());
             c_args.push(c_arg);
         }
@@ -95,7 +119,9 @@ impl DxcIndex {
             )
         }
         .result()?;
-        Ok(DxcTranslationUnit::new(tu.unwrap()))
+        Ok(DxcTranslationUnit::new(
+            tu.ok_or(HassleError::NullOutput("IDxcTranslationUnit"))?,
+        ))
     }
 }
 
@@ -126,16 +152,256 @@ impl DxcTranslationUnit {
     pub fn get_file(&self, name: &[u8]) -> Result<DxcFile> {
         let mut file = None;
         unsafe { self.inner.get_file(name.as_ptr(), &mut file) }.result()?;
-        Ok(DxcFile::new(file.unwrap()))
+        Ok(DxcFile::new(
+            file.ok_or(HassleError::NullOutput("IDxcFile"))?,
+        ))
     }
 
     pub fn get_cursor(&self) -> Result<DxcCursor> {
         let mut cursor = None;
         unsafe { self.inner.get_cursor(&mut cursor) }.result()?;
-        Ok(DxcCursor::new(cursor.unwrap()))
+        Ok(DxcCursor::new(
+            cursor.ok_or(HassleError::NullOutput("IDxcCursor"))?,
+        ))
+    }
+
+    pub fn get_num_diagnostics(&self) -> Result<u32> {
+        let mut count: u32 = 0;
+        unsafe { self.inner.get_num_diagnostics(&mut count) }.result_with_success(count)
+    }
+
+    pub fn get_diagnostic(&self, index: u32) -> Result<DxcDiagnostic> {
+        let mut diagnostic = None;
+        unsafe { self.inner.get_diagnostic(index, &mut diagnostic) }.result()?;
+        Ok(DxcDiagnostic::new(
+            diagnostic.ok_or(HassleError::NullOutput("IDxcDiagnostic"))?,
+        ))
+    }
+
+    /// Every diagnostic reported while parsing or reparsing this translation unit.
+    pub fn diagnostics(&self) -> Result<Vec<DxcDiagnostic>> {
+        (0..self.get_num_diagnostics()?)
+            .map(|index| self.get_diagnostic(index))
+            .collect()
+    }
+
+    /// Splits the source in `range` into tokens, such as the extent of a cursor from
+    /// [`DxcCursor::get_extent()`].
+    pub fn tokenize(&self, range: &DxcSourceRange) -> Result<Vec<DxcToken>> {
+        let mut result: *mut IDxcToken = std::ptr::null_mut();
+        let mut result_length: u32 = 0;
+
+        unsafe {
+            self.inner
+                .tokenize(&range.inner, &mut result, &mut result_length)
+        }
+        .result()?;
+
+        if result.is_null() {
+            return Ok(vec![]);
+        }
+
+        // tokenize allocates a buffer to pass the result in, see get_children for why this takes
+        // ownership of the `IDxcToken`(s) through a ManuallyDrop vector
+        let tokens = ManuallyDrop::new(unsafe {
+            Vec::from_raw_parts(result, result_length as usize, result_length as usize)
+        })
+        .drain(..)
+        .map(|token| DxcToken::from_token(&token))
+        .collect::<Result<Vec<_>>>();
+
+        unsafe { CoTaskMemFree(result.cast()) };
+        tokens
+    }
+
+    /// Every file included while parsing this translation unit, in the order they were
+    /// included.
+    pub fn inclusions(&self) -> Result<Vec<DxcInclusion>> {
+        let mut result: *mut IDxcInclusion = std::ptr::null_mut();
+        let mut result_length: u32 = 0;
+
+        unsafe {
+            self.inner
+                .get_inclusion_list(&mut result_length, &mut result)
+        }
+        .result()?;
+
+        if result.is_null() {
+            return Ok(vec![]);
+        }
+
+        // get_inclusion_list allocates a buffer to pass the result in, see get_children for why
+        // this takes ownership of the `IDxcInclusion`(s) through a ManuallyDrop vector
+        let inclusions = ManuallyDrop::new(unsafe {
+            Vec::from_raw_parts(result, result_length as usize, result_length as usize)
+        })
+        .drain(..)
+        .map(|inclusion| DxcInclusion::from_inclusion(&inclusion))
+        .collect::<Result<Vec<_>>>();
+
+        unsafe { CoTaskMemFree(result.cast()) };
+        inclusions
     }
 }
 
+/// A file included by a translation unit, see [`DxcTranslationUnit::inclusions()`].
+pub struct DxcInclusion {
+    pub file: DxcFile,
+    /// The locations of the `#include` directives that led to `file`, starting with the
+    /// innermost.  Empty for the main file of the translation unit.
+    pub stack: Vec<DxcSourceLocation>,
+}
+
+impl DxcInclusion {
+    fn from_inclusion(inclusion: &IDxcInclusion) -> Result<Self> {
+        let mut file = None;
+        unsafe { inclusion.get_included_file(&mut file) }.result()?;
+
+        let mut stack_length: u32 = 0;
+        unsafe { inclusion.get_stack_length(&mut stack_length) }.result()?;
+
+        let stack = (0..stack_length)
+            .map(|index| {
+                let mut location = None;
+                unsafe { inclusion.get_stack_item(index, &mut location) }.result()?;
+                Ok(DxcSourceLocation::new(
+                    location.ok_or(HassleError::NullOutput("IDxcSourceLocation"))?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            file: DxcFile::new(file.ok_or(HassleError::NullOutput("IDxcFile"))?),
+            stack,
+        })
+    }
+}
+
+/// A token of a translation unit, see [`DxcTranslationUnit::tokenize()`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DxcToken {
+    pub kind: DxcTokenKind,
+    pub spelling: String,
+    /// Byte offset of the first character of the token in its file.
+    pub start_offset: u32,
+    /// Byte offset just past the last character of the token in its file.
+    pub end_offset: u32,
+}
+
+impl DxcToken {
+    fn from_token(token: &IDxcToken) -> Result<Self> {
+        let mut kind = DxcTokenKind::UNKNOWN;
+        unsafe { token.get_kind(&mut kind) }.result()?;
+
+        let mut spelling: LPSTR = std::ptr::null_mut();
+        unsafe { token.get_spelling(&mut spelling) }.result()?;
+        let spelling = take_lpstr(spelling)?;
+
+        let mut extent = None;
+        unsafe { token.get_extent(&mut extent) }.result()?;
+        let DxcSourceOffsets {
+            start_offset,
+            end_offset,
+        } = DxcSourceRange::new(extent.ok_or(HassleError::NullOutput("IDxcSourceRange"))?)
+            .get_offsets()?;
+
+        Ok(Self {
+            kind,
+            spelling,
+            start_offset,
+            end_offset,
+        })
+    }
+}
+
+pub struct DxcDiagnostic {
+    inner: IDxcDiagnostic,
+}
+
+impl DxcDiagnostic {
+    fn new(inner: IDxcDiagnostic) -> Self {
+        DxcDiagnostic { inner }
+    }
+
+    /// Formats the diagnostic like the compiler's command line output, including the parts
+    /// selected by `options`.  See [`DxcIntellisense::get_default_diagnostic_display_options()`].
+    pub fn format(&self, options: DxcDiagnosticDisplayOptions) -> Result<String> {
+        let mut result: LPSTR = std::ptr::null_mut();
+        unsafe { self.inner.format_diagnostic(options, &mut result) }.result()?;
+        take_lpstr(result)
+    }
+
+    pub fn get_severity(&self) -> Result<DxcDiagnosticSeverity> {
+        let mut severity = DxcDiagnosticSeverity::IGNORED;
+        unsafe { self.inner.get_severity(&mut severity) }.result_with_success(severity)
+    }
+
+    pub fn get_location(&self) -> Result<DxcSourceLocation> {
+        let mut location = None;
+        unsafe { self.inner.get_location(&mut location) }.result()?;
+        Ok(DxcSourceLocation::new(
+            location.ok_or(HassleError::NullOutput("IDxcSourceLocation"))?,
+        ))
+    }
+
+    /// The message of the diagnostic, without location or severity.
+    pub fn get_spelling(&self) -> Result<String> {
+        let mut spelling: LPSTR = std::ptr::null_mut();
+        unsafe { self.inner.get_spelling(&mut spelling) }.result()?;
+        take_lpstr(spelling)
+    }
+
+    pub fn get_category_text(&self) -> Result<String> {
+        let mut category: LPSTR = std::ptr::null_mut();
+        unsafe { self.inner.get_category_text(&mut category) }.result()?;
+        take_lpstr(category)
+    }
+
+    /// The source ranges highlighted by this diagnostic.
+    pub fn get_ranges(&self) -> Result<Vec<DxcSourceRange>> {
+        let mut count: u32 = 0;
+        unsafe { self.inner.get_num_ranges(&mut count) }.result()?;
+
+        (0..count)
+            .map(|index| {
+                let mut range = None;
+                unsafe { self.inner.get_range_at(index, &mut range) }.result()?;
+                Ok(DxcSourceRange::new(
+                    range.ok_or(HassleError::NullOutput("IDxcSourceRange"))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Replacements suggested to resolve this diagnostic.
+    pub fn get_fix_its(&self) -> Result<Vec<DxcFixIt>> {
+        let mut count: u32 = 0;
+        unsafe { self.inner.get_num_fix_its(&mut count) }.result()?;
+
+        (0..count)
+            .map(|index| {
+                let mut range = None;
+                let mut text: LPSTR = std::ptr::null_mut();
+                unsafe { self.inner.get_fix_it_at(index, &mut range, &mut text) }.result()?;
+                Ok(DxcFixIt {
+                    range: DxcSourceRange::new(
+                        range.ok_or(HassleError::NullOutput("IDxcSourceRange"))?,
+                    ),
+                    text: take_lpstr(text)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A suggested edit that replaces the source in `range` with `text`.  An empty range inserts
+/// `text`, an empty `text` removes the source in `range`.
+#[derive(Debug)]
+pub struct DxcFixIt {
+    pub range: DxcSourceRange,
+    pub text: String,
+}
+
 pub struct DxcCursor {
     inner: IDxcCursor,
 }
@@ -155,6 +421,10 @@ impl DxcCursor {
         }
         .result()?;
 
+        if result.is_null() {
+            return Ok(vec![]);
+        }
+
         // get_children allocates a buffer to pass the result in.
         // Create a vector so that we get ownership of the `IDxcCursor(s) (received from get_children), instead of
         // having to clone (copy is intentionally not implemented) them and leaving unowned COM references alive.
@@ -188,25 +458,29 @@ impl DxcCursor {
     pub fn get_extent(&self) -> Result<DxcSourceRange> {
         let mut range = None;
         unsafe { self.inner.get_extent(&mut range) }.result()?;
-        Ok(DxcSourceRange::new(range.unwrap()))
+        Ok(DxcSourceRange::new(
+            range.ok_or(HassleError::NullOutput("IDxcSourceRange"))?,
+        ))
     }
 
     pub fn get_location(&self) -> Result<DxcSourceLocation> {
         let mut location = None;
         unsafe { self.inner.get_location(&mut location) }.result()?;
-        Ok(DxcSourceLocation::new(location.unwrap()))
+        Ok(DxcSourceLocation::new(
+            location.ok_or(HassleError::NullOutput("IDxcSourceLocation"))?,
+        ))
     }
 
     pub fn get_display_name(&self) -> Result<String> {
         let mut name: BSTR = std::ptr::null_mut();
         unsafe { self.inner.get_display_name(&mut name) }.result()?;
-        Ok(crate::utils::from_bstr(name))
+        crate::utils::from_bstr(name)
     }
 
     pub fn get_formatted_name(&self, formatting: DxcCursorFormatting) -> Result<String> {
         let mut name: BSTR = std::ptr::null_mut();
         unsafe { self.inner.get_formatted_name(formatting, &mut name) }.result()?;
-        Ok(crate::utils::from_bstr(name))
+        crate::utils::from_bstr(name)
     }
 
     pub fn get_qualified_name(&self, include_template_args: bool) -> Result<String> {
@@ -216,7 +490,7 @@ impl DxcCursor {
                 .get_qualified_name(include_template_args, &mut name)
         }
         .result()?;
-        Ok(crate::utils::from_bstr(name))
+        crate::utils::from_bstr(name)
     }
 
     pub fn get_kind(&self) -> Result<DxcCursorKind> {
@@ -233,19 +507,25 @@ impl DxcCursor {
     pub fn get_semantic_parent(&self) -> Result<DxcCursor> {
         let mut inner = None;
         unsafe { self.inner.get_semantic_parent(&mut inner) }.result()?;
-        Ok(DxcCursor::new(inner.unwrap()))
+        Ok(DxcCursor::new(
+            inner.ok_or(HassleError::NullOutput("IDxcCursor"))?,
+        ))
     }
 
     pub fn get_lexical_parent(&self) -> Result<DxcCursor> {
         let mut inner = None;
         unsafe { self.inner.get_lexical_parent(&mut inner) }.result()?;
-        Ok(DxcCursor::new(inner.unwrap()))
+        Ok(DxcCursor::new(
+            inner.ok_or(HassleError::NullOutput("IDxcCursor"))?,
+        ))
     }
 
     pub fn get_cursor_type(&self) -> Result<DxcType> {
         let mut inner = None;
         unsafe { self.inner.get_cursor_type(&mut inner) }.result()?;
-        Ok(DxcType::new(inner.unwrap()))
+        Ok(DxcType::new(
+            inner.ok_or(HassleError::NullOutput("IDxcType"))?,
+        ))
     }
 
     pub fn get_num_arguments(&self) -> Result<i32> {
@@ -257,19 +537,25 @@ impl DxcCursor {
     pub fn get_argument_at(&self, index: i32) -> Result<DxcCursor> {
         let mut inner = None;
         unsafe { self.inner.get_argument_at(index, &mut inner) }.result()?;
-        Ok(DxcCursor::new(inner.unwrap()))
+        Ok(DxcCursor::new(
+            inner.ok_or(HassleError::NullOutput("IDxcCursor"))?,
+        ))
     }
 
     pub fn get_referenced_cursor(&self) -> Result<DxcCursor> {
         let mut inner = None;
         unsafe { self.inner.get_referenced_cursor(&mut inner) }.result()?;
-        Ok(DxcCursor::new(inner.unwrap()))
+        Ok(DxcCursor::new(
+            inner.ok_or(HassleError::NullOutput("IDxcCursor"))?,
+        ))
     }
 
     pub fn get_definition_cursor(&self) -> Result<DxcCursor> {
         let mut inner = None;
         unsafe { self.inner.get_definition_cursor(&mut inner) }.result()?;
-        Ok(DxcCursor::new(inner.unwrap()))
+        Ok(DxcCursor::new(
+            inner.ok_or(HassleError::NullOutput("IDxcCursor"))?,
+        ))
     }
 
     pub fn find_references_in_file(
@@ -292,6 +578,10 @@ impl DxcCursor {
         }
         .result()?;
 
+        if result.is_null() {
+            return Ok(vec![]);
+        }
+
         // find_references_in_file allocates a buffer to pass the result in.
         // Create a vector so that we get ownership of the `IDxcCursor(s) (received from find_references_in_file), instead
         // of having to clone (copy is intentionally not implemented) them and leaving unowned COM references alive.
@@ -311,7 +601,7 @@ impl DxcCursor {
     pub fn get_spelling(&self) -> Result<String> {
         let mut spelling: LPSTR = std::ptr::null_mut();
         unsafe { self.inner.get_spelling(&mut spelling) }.result()?;
-        Ok(crate::utils::from_lpstr(spelling))
+        crate::utils::from_lpstr(spelling)
     }
 
     pub fn is_equal_to(&self, other: &DxcCursor) -> Result<bool> {
@@ -332,7 +622,9 @@ impl DxcCursor {
     pub fn get_snapped_child(&self, location: &DxcSourceLocation) -> Result<DxcCursor> {
         let mut inner = None;
         unsafe { self.inner.get_snapped_child(&location.inner, &mut inner) }.result()?;
-        Ok(DxcCursor::new(inner.unwrap()))
+        Ok(DxcCursor::new(
+            inner.ok_or(HassleError::NullOutput("IDxcCursor"))?,
+        ))
     }
 
     pub fn get_source<'a>(&self, source: &'a str) -> Result<&'a str> {
@@ -360,8 +652,8 @@ impl DxcType {
 
     pub fn get_spelling(&self) -> Result<String> {
         let mut spelling: LPSTR = std::ptr::null_mut();
-        unsafe { self.inner.get_spelling(&mut spelling) }
-            .result_with_success(crate::utils::from_lpstr(spelling))
+        unsafe { self.inner.get_spelling(&mut spelling) }.result()?;
+        crate::utils::from_lpstr(spelling)
     }
 }
 
@@ -381,6 +673,38 @@ impl DxcSourceLocation {
     fn new(inner: IDxcSourceLocation) -> Self {
         DxcSourceLocation { inner }
     }
+
+    pub fn is_null(&self) -> Result<bool> {
+        let mut result: bool = false;
+        unsafe { IDxcSourceLocation::is_null(&self.inner, &mut result) }.result_with_success(result)
+    }
+
+    /// The file, 1-based line and column and byte offset this location points to.
+    pub fn get_spelling_location(&self) -> Result<DxcSpellingLocation> {
+        let mut file = None;
+        let mut line: u32 = 0;
+        let mut column: u32 = 0;
+        let mut offset: u32 = 0;
+        unsafe {
+            self.inner
+                .get_spelling_location(&mut file, &mut line, &mut column, &mut offset)
+        }
+        .result()?;
+        Ok(DxcSpellingLocation {
+            file: file.map(DxcFile::new),
+            line,
+            column,
+            offset,
+        })
+    }
+}
+
+pub struct DxcSpellingLocation {
+    /// [`None`] for null locations.
+    pub file: Option<DxcFile>,
+    pub line: u32,
+    pub column: u32,
+    pub offset: u32,
 }
 
 #[derive(Debug)]
@@ -418,6 +742,27 @@ impl DxcSourceRange {
     fn new(inner: IDxcSourceRange) -> Self {
         DxcSourceRange { inner }
     }
+
+    pub fn is_null(&self) -> Result<bool> {
+        let mut result: bool = false;
+        unsafe { IDxcSourceRange::is_null(&self.inner, &mut result) }.result_with_success(result)
+    }
+
+    pub fn get_start(&self) -> Result<DxcSourceLocation> {
+        let mut location = None;
+        unsafe { self.inner.get_start(&mut location) }.result()?;
+        Ok(DxcSourceLocation::new(
+            location.ok_or(HassleError::NullOutput("IDxcSourceLocation"))?,
+        ))
+    }
+
+    pub fn get_end(&self) -> Result<DxcSourceLocation> {
+        let mut location = None;
+        unsafe { self.inner.get_end(&mut location) }.result()?;
+        Ok(DxcSourceLocation::new(
+            location.ok_or(HassleError::NullOutput("IDxcSourceLocation"))?,
+        ))
+    }
 }
 
 pub struct DxcFile {
@@ -428,18 +773,23 @@ impl DxcFile {
     fn new(inner: IDxcFile) -> Self {
         DxcFile { inner }
     }
+
+    pub fn get_name(&self) -> Result<String> {
+        let mut name: LPSTR = std::ptr::null_mut();
+        unsafe { self.inner.get_name(&mut name) }.result()?;
+        take_lpstr(name)
+    }
+
+    pub fn is_equal_to(&self, other: &DxcFile) -> Result<bool> {
+        let mut result: bool = false;
+        unsafe { self.inner.is_equal_to(&other.inner, &mut result) }.result_with_success(result)
+    }
 }
 
 impl Dxc {
     pub fn create_intellisense(&self) -> Result<DxcIntellisense> {
-        let mut intellisense = None;
-
-        self.get_dxc_create_instance()?(
-            &CLSID_DxcIntelliSense,
-            &IDxcIntelliSense::IID,
-            &mut intellisense,
-        )
-        .result()?;
-        Ok(DxcIntellisense::new(intellisense.unwrap()))
+        Ok(DxcIntellisense::new(
+            self.create_instance(&CLSID_DxcIntelliSense)?,
+        ))
     }
 }
@@ -4,6 +4,9 @@
     clippy::transmute_ptr_to_ptr, // Introduced by com-rs
     clippy::too_many_arguments, // We're wrapping an API outside of our control
     clippy::uninlined_format_args, // Unfavourable format; implies unneeded MSRV bump
+    clippy::unnecessary_map_or, // `Option::is_some_and()` implies unneeded MSRV bump
+    clippy::io_other_error, // `std::io::Error::other()` implies unneeded MSRV bump
+    clippy::derivable_impls, // `#[default]` on enum variants implies unneeded MSRV bump
 )]
 
 //! # Hassle
@@ -38,16 +41,37 @@
 //! );
 //! ```
 
+pub mod build;
+pub mod cache;
+pub mod compare;
+pub mod diagnostics;
+pub mod discovery;
 pub mod fake_sign;
 pub mod ffi;
+pub mod include;
 pub mod os;
+pub mod permutation;
+pub mod pool;
 pub mod utils;
 pub mod wrapper;
 
 pub mod intellisense;
 
+pub use crate::cache::{CacheStatus, ShaderCache};
+pub use crate::compare::{CompileComparison, Difference, DxcVersions, VersionResult, VersionedDxc};
+pub use crate::diagnostics::{
+    parse_diagnostics, Diagnostic, DiagnosticRenderer, IncludeLocation, Severity,
+};
+pub use crate::discovery::{LibraryOrigin, LibrarySearch, LoadAttempt};
 pub use crate::ffi::*;
+pub use crate::include::{
+    Dependencies, DependencyTracker, FileSystemIncludeHandler, IncludeSource, VirtualIncludeHandler,
+};
+pub use crate::permutation::{DefineAxis, PermutationKey, ShaderPermutations};
+pub use crate::pool::{CompileJob, CompilerPool};
 pub use crate::utils::{
-    compile_hlsl, fake_sign_dxil_in_place, validate_dxil, HassleError, OperationOutput, Result,
+    compile_hlsl, compile_hlsl_with_dependencies, fake_sign_dxil_in_place, sign_dxil,
+    validate_dxil, HassleError, OperationBlob, OperationOutput, Result, SignedDxil, SigningMethod,
+    SigningPolicy,
 };
 pub use crate::wrapper::*;
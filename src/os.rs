@@ -104,8 +104,157 @@ impl std::fmt::Debug for HRESULT {
     }
 }
 
+/// Formats as the symbolic name followed by the value, such as `E_INVALIDARG (0x80070057)`, or
+/// only the value if the code is not known.
 impl std::fmt::Display for HRESULT {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:#x}", self.0)
+        match self.name() {
+            Some(name) => write!(f, "{} ({:#x})", name, self.0),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+/// Facility of the error codes defined by DXC in `dxc/Support/ErrorCodes.h`.
+pub const FACILITY_DXC: u16 = 0xAA;
+
+/// Known codes with their symbolic name and description.
+#[rustfmt::skip]
+const KNOWN_HRESULTS: &[(u32, &str, &str)] = &[
+    (0x0000_0000, "S_OK", "Success"),
+    (0x0000_0001, "S_FALSE", "Success, with a false result"),
+    (0x8000_000B, "E_BOUNDS", "Access outside the valid range"),
+    (0x8000_4001, "E_NOTIMPL", "Not implemented"),
+    (0x8000_4002, "E_NOINTERFACE", "No such interface supported"),
+    (0x8000_4003, "E_POINTER", "Invalid pointer"),
+    (0x8000_4004, "E_ABORT", "Operation aborted"),
+    (0x8000_4005, "E_FAIL", "Unspecified failure"),
+    (0x8000_FFFF, "E_UNEXPECTED", "Catastrophic failure"),
+    (0x8004_0111, "CLASS_E_CLASSNOTAVAILABLE", "Class not available"),
+    (0x8004_0154, "REGDB_E_CLASSNOTREG", "Class not registered"),
+    (0x8007_0002, "ERROR_FILE_NOT_FOUND", "The system cannot find the file specified"),
+    (0x8007_0003, "ERROR_PATH_NOT_FOUND", "The system cannot find the path specified"),
+    (0x8007_0005, "E_ACCESSDENIED", "Access denied"),
+    (0x8007_0006, "E_HANDLE", "Invalid handle"),
+    (0x8007_000D, "ERROR_INVALID_DATA", "The data is invalid"),
+    (0x8007_000E, "E_OUTOFMEMORY", "Out of memory"),
+    (0x8007_0032, "ERROR_NOT_SUPPORTED", "The request is not supported"),
+    (0x8007_0057, "E_INVALIDARG", "One or more arguments are invalid"),
+    (0x8007_007A, "ERROR_INSUFFICIENT_BUFFER", "The data area passed to a system call is too small"),
+    (0x8007_0216, "ERROR_ARITHMETIC_OVERFLOW", "Arithmetic result exceeded 32 bits"),
+    (0x8007_023E, "ERROR_UNHANDLED_EXCEPTION", "Unhandled exception"),
+    (0x8007_139F, "E_NOT_VALID_STATE", "The group or resource is not in the correct state"),
+    (0x80AA_0001, "DXC_E_OVERLAPPING_SEMANTICS", "Overlapping semantics were found"),
+    (0x80AA_0002, "DXC_E_MULTIPLE_DEPTH_SEMANTICS", "Multiple depth semantics were found"),
+    (0x80AA_0003, "DXC_E_INPUT_FILE_TOO_LARGE", "Input file is too large"),
+    (0x80AA_0004, "DXC_E_INCORRECT_DXBC", "Error parsing DXBC container"),
+    (0x80AA_0005, "DXC_E_ERROR_PARSING_DXBC_BYTECODE", "Error parsing DXBC bytecode"),
+    (0x80AA_0006, "DXC_E_DATA_TOO_LARGE", "Data is too large"),
+    (0x80AA_0007, "DXC_E_INCOMPATIBLE_CONVERTER_OPTIONS", "Incompatible converter options"),
+    (0x80AA_0008, "DXC_E_IRREDUCIBLE_CFG", "Irreducible control flow graph"),
+    (0x80AA_0009, "DXC_E_IR_VERIFICATION_FAILED", "IR verification failed"),
+    (0x80AA_000A, "DXC_E_SCOPE_NESTED_FAILED", "Scope-nested control flow recovery failed"),
+    (0x80AA_000B, "DXC_E_NOT_SUPPORTED", "Operation is not supported"),
+    (0x80AA_000C, "DXC_E_STRING_ENCODING_FAILED", "Unable to encode string"),
+    (0x80AA_000D, "DXC_E_CONTAINER_INVALID", "DXIL container is invalid"),
+    (0x80AA_000E, "DXC_E_CONTAINER_MISSING_DXIL", "DXIL container is missing the DXIL part"),
+    (0x80AA_000F, "DXC_E_INCORRECT_DXIL_METADATA", "Unable to parse DxilModule metadata"),
+    (0x80AA_0010, "DXC_E_INCORRECT_DDI_SIGNATURE", "Error parsing DDI signature"),
+    (0x80AA_0011, "DXC_E_DUPLICATE_PART", "Duplicate part exists in DXIL container"),
+    (0x80AA_0012, "DXC_E_MISSING_PART", "Error finding part in DXIL container"),
+    (0x80AA_0013, "DXC_E_MALFORMED_CONTAINER", "Malformed DXIL container"),
+    (0x80AA_0014, "DXC_E_INCORRECT_ROOT_SIGNATURE", "Incorrect root signature for shader"),
+    (0x80AA_0015, "DXC_E_CONTAINER_MISSING_DEBUG", "DXIL container is missing the DebugInfo part"),
+    (0x80AA_0016, "DXC_E_LLVM_FATAL_ERROR", "Unexpected failure in LLVM"),
+    (0x80AA_0017, "DXC_E_LLVM_UNREACHABLE", "LLVM reached unreachable code"),
+    (0x80AA_0018, "DXC_E_LLVM_CAST_ERROR", "LLVM cast error"),
+];
+
+impl HRESULT {
+    fn known(&self) -> Option<&'static (u32, &'static str, &'static str)> {
+        KNOWN_HRESULTS
+            .iter()
+            .find(|(code, _, _)| *code == self.0 as u32)
+    }
+
+    /// Returns the symbolic name of common COM and DXC codes.
+    ///
+    /// ```
+    /// use hassle_rs::os::HRESULT;
+    ///
+    /// let hr = HRESULT(0x80AA0014_u32 as i32);
+    /// assert_eq!(hr.name(), Some("DXC_E_INCORRECT_ROOT_SIGNATURE"));
+    /// assert_eq!(hr.to_string(), "DXC_E_INCORRECT_ROOT_SIGNATURE (0x80aa0014)");
+    /// assert!(hr.is_dxc_error());
+    /// ```
+    pub fn name(&self) -> Option<&'static str> {
+        self.known().map(|(_, name, _)| *name)
+    }
+
+    /// Returns a short, human-readable description of common COM and DXC codes.
+    pub fn description(&self) -> Option<&'static str> {
+        self.known().map(|(_, _, description)| *description)
+    }
+
+    /// The facility that defined this code, such as `7` for Win32 errors or [`FACILITY_DXC`].
+    pub fn facility(&self) -> u16 {
+        ((self.0 as u32 >> 16) & 0x1fff) as u16
+    }
+
+    /// The facility-specific part of this code.
+    pub fn code(&self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Returns `true` for error codes defined by DXC.
+    pub fn is_dxc_error(&self) -> bool {
+        self.is_err() && self.facility() == FACILITY_DXC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_codes_are_named() {
+        let hr = HRESULT(0x8007_0057_u32 as i32);
+        assert_eq!(hr.name(), Some("E_INVALIDARG"));
+        assert_eq!(hr.description(), Some("One or more arguments are invalid"));
+        assert_eq!(hr.to_string(), "E_INVALIDARG (0x80070057)");
+        assert_eq!((hr.facility(), hr.code()), (7, 0x57));
+        assert!(hr.is_err());
+        assert!(!hr.is_dxc_error());
+
+        assert_eq!(HRESULT(0).name(), Some("S_OK"));
+        assert!(!HRESULT(0).is_err());
+    }
+
+    #[test]
+    fn dxc_codes_are_recognized() {
+        let hr = HRESULT(0x80AA_0013_u32 as i32);
+        assert_eq!(hr.name(), Some("DXC_E_MALFORMED_CONTAINER"));
+        assert_eq!((hr.facility(), hr.code()), (FACILITY_DXC, 0x13));
+        assert!(hr.is_dxc_error());
+
+        // Unknown codes in the DXC facility are still DXC errors
+        let unknown = HRESULT(0x80AA_0FFF_u32 as i32);
+        assert_eq!(unknown.name(), None);
+        assert!(unknown.is_dxc_error());
+        assert_eq!(unknown.to_string(), "0x80aa0fff");
+        assert_eq!(format!("{:?}", unknown), "0x80aa0fff");
+    }
+
+    #[test]
+    fn known_codes_are_unique() {
+        for (i, (code, name, _)) in KNOWN_HRESULTS.iter().enumerate() {
+            assert!(
+                KNOWN_HRESULTS[i + 1..]
+                    .iter()
+                    .all(|(other_code, other_name, _)| other_code != code && other_name != name),
+                "{} is listed twice",
+                name
+            );
+        }
     }
 }
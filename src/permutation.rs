@@ -0,0 +1,312 @@
+//! Expansion of shader feature toggles into permutations and their batch compilation.
+
+use crate::pool::{CompileJob, CompilerPool};
+use crate::utils::{OperationOutput, Result};
+use std::fmt;
+
+/// A define that is toggled between permutations.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DefineAxis {
+    /// Either left undefined, or defined as `1`.
+    Bool(String),
+    /// Defined as each of the listed values in turn.
+    Enum { name: String, values: Vec<String> },
+}
+
+impl DefineAxis {
+    pub fn bool(name: impl Into<String>) -> Self {
+        Self::Bool(name.into())
+    }
+
+    pub fn enumeration<I: IntoIterator<Item = S>, S: Into<String>>(
+        name: impl Into<String>,
+        values: I,
+    ) -> Self {
+        Self::Enum {
+            name: name.into(),
+            values: values.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Bool(name) | Self::Enum { name, .. } => name,
+        }
+    }
+
+    /// Number of distinct settings of this axis.
+    fn len(&self) -> usize {
+        match self {
+            Self::Bool(_) => 2,
+            Self::Enum { values, .. } => values.len(),
+        }
+    }
+
+    /// Returns the define for setting `index`, or [`None`] if the define is left out.
+    fn define(&self, index: usize) -> Option<(String, Option<String>)> {
+        match self {
+            Self::Bool(name) => (index == 1).then(|| (name.clone(), None)),
+            Self::Enum { name, values } => Some((name.clone(), Some(values[index].clone()))),
+        }
+    }
+}
+
+/// Identifies a single permutation.
+///
+/// [`Self::defines`] uses the same convention as [`CompileJob::defines`]: enabled
+/// [`DefineAxis::Bool`] axes have a value of [`None`] (defined as `1`), disabled ones are absent.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PermutationKey {
+    pub entry_point: String,
+    pub target_profile: String,
+    pub defines: Vec<(String, Option<String>)>,
+}
+
+impl PermutationKey {
+    /// Returns `true` if `name` is defined in this permutation, regardless of its value.
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.defines.iter().any(|(n, _)| n == name)
+    }
+
+    /// Returns the value of an [`DefineAxis::Enum`] axis.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.defines
+            .iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+}
+
+impl fmt::Display for PermutationKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}[", self.entry_point, self.target_profile)?;
+        for (i, (name, value)) in self.defines.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            match value {
+                Some(value) => write!(f, "{}={}", name, value)?,
+                None => f.write_str(name)?,
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+type ExcludeFn = dyn Fn(&PermutationKey) -> bool + Send + Sync;
+
+/// Enumerates the cartesian product of entry points, target profiles and [`DefineAxis`] settings
+/// for a single shader source.
+///
+/// ```
+/// use hassle_rs::permutation::{DefineAxis, ShaderPermutations};
+///
+/// let permutations = ShaderPermutations::new("lit.hlsl", "/* ... */")
+///     .with_entry_point("main")
+///     .with_target_profile("ps_6_5")
+///     .with_axis(DefineAxis::bool("USE_SHADOWS"))
+///     .with_axis(DefineAxis::enumeration("QUALITY", ["LOW", "HIGH"]))
+///     .exclude(|key| key.is_defined("USE_SHADOWS") && key.value("QUALITY") == Some("LOW"));
+///
+/// let keys = permutations
+///     .keys()
+///     .iter()
+///     .map(ToString::to_string)
+///     .collect::<Vec<_>>();
+/// assert_eq!(
+///     keys,
+///     [
+///         "main@ps_6_5[QUALITY=LOW]",
+///         "main@ps_6_5[QUALITY=HIGH]",
+///         "main@ps_6_5[USE_SHADOWS,QUALITY=HIGH]",
+///     ]
+/// );
+/// ```
+pub struct ShaderPermutations {
+    source_name: String,
+    source: String,
+    entry_points: Vec<String>,
+    target_profiles: Vec<String>,
+    axes: Vec<DefineAxis>,
+    args: Vec<String>,
+    exclusions: Vec<Box<ExcludeFn>>,
+}
+
+impl ShaderPermutations {
+    pub fn new(source_name: impl Into<String>, source: impl Into<String>) -> Self {
+        Self {
+            source_name: source_name.into(),
+            source: source.into(),
+            entry_points: vec![],
+            target_profiles: vec![],
+            axes: vec![],
+            args: vec![],
+            exclusions: vec![],
+        }
+    }
+
+    pub fn with_entry_point(mut self, entry_point: impl Into<String>) -> Self {
+        self.entry_points.push(entry_point.into());
+        self
+    }
+
+    pub fn with_target_profile(mut self, target_profile: impl Into<String>) -> Self {
+        self.target_profiles.push(target_profile.into());
+        self
+    }
+
+    pub fn with_axis(mut self, axis: DefineAxis) -> Self {
+        self.axes.push(axis);
+        self
+    }
+
+    /// Adds an argument that is passed to every permutation.
+    pub fn with_arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Skips every permutation for which `predicate` returns `true`.
+    pub fn exclude<F: Fn(&PermutationKey) -> bool + Send + Sync + 'static>(
+        mut self,
+        predicate: F,
+    ) -> Self {
+        self.exclusions.push(Box::new(predicate));
+        self
+    }
+
+    /// Returns every permutation that is not excluded, varying the last axis fastest.
+    pub fn keys(&self) -> Vec<PermutationKey> {
+        let mut keys = vec![];
+
+        if self.axes.iter().any(|axis| axis.len() == 0) {
+            return keys;
+        }
+
+        for entry_point in &self.entry_points {
+            for target_profile in &self.target_profiles {
+                let mut settings = vec![0; self.axes.len()];
+                loop {
+                    let key = PermutationKey {
+                        entry_point: entry_point.clone(),
+                        target_profile: target_profile.clone(),
+                        defines: self
+                            .axes
+                            .iter()
+                            .zip(&settings)
+                            .filter_map(|(axis, &setting)| axis.define(setting))
+                            .collect(),
+                    };
+                    if !self.exclusions.iter().any(|exclude| exclude(&key)) {
+                        keys.push(key);
+                    }
+
+                    // Advance the settings like an odometer
+                    let mut axis = self.axes.len();
+                    loop {
+                        if axis == 0 {
+                            break;
+                        }
+                        axis -= 1;
+                        settings[axis] += 1;
+                        if settings[axis] < self.axes[axis].len() {
+                            break;
+                        }
+                        settings[axis] = 0;
+                    }
+                    if settings.iter().all(|&s| s == 0) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        keys
+    }
+
+    /// Returns a [`CompileJob`] for every permutation in [`Self::keys()`].
+    pub fn jobs(&self) -> Vec<(PermutationKey, CompileJob)> {
+        self.keys()
+            .into_iter()
+            .map(|key| {
+                let job = CompileJob {
+                    source_name: self.source_name.clone(),
+                    source: self.source.clone(),
+                    entry_point: key.entry_point.clone(),
+                    target_profile: key.target_profile.clone(),
+                    args: self.args.clone(),
+                    defines: key.defines.clone(),
+                };
+                (key, job)
+            })
+            .collect()
+    }
+
+    /// Compiles every permutation on `pool`, labelling each result with its key.
+    pub fn compile(&self, pool: &CompilerPool) -> Vec<(PermutationKey, Result<OperationOutput>)> {
+        let (keys, jobs): (Vec<_>, Vec<_>) = self.jobs().into_iter().unzip();
+        keys.into_iter().zip(pool.compile(&jobs)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(permutations: &ShaderPermutations) -> Vec<String> {
+        permutations
+            .keys()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn without_axes_yields_one_key_per_entry_point_and_profile() {
+        let permutations = ShaderPermutations::new("a.hlsl", "")
+            .with_entry_point("vs")
+            .with_entry_point("ps")
+            .with_target_profile("6_5")
+            .with_target_profile("6_6");
+        assert_eq!(
+            keys(&permutations),
+            ["vs@6_5[]", "vs@6_6[]", "ps@6_5[]", "ps@6_6[]"]
+        );
+    }
+
+    #[test]
+    fn odometer_varies_last_axis_fastest() {
+        let permutations = ShaderPermutations::new("a.hlsl", "")
+            .with_entry_point("main")
+            .with_target_profile("cs_6_5")
+            .with_axis(DefineAxis::enumeration("Q", ["0", "1", "2"]))
+            .with_axis(DefineAxis::bool("B"));
+        assert_eq!(
+            keys(&permutations),
+            [
+                "main@cs_6_5[Q=0]",
+                "main@cs_6_5[Q=0,B]",
+                "main@cs_6_5[Q=1]",
+                "main@cs_6_5[Q=1,B]",
+                "main@cs_6_5[Q=2]",
+                "main@cs_6_5[Q=2,B]",
+            ]
+        );
+    }
+
+    #[test]
+    fn single_valued_and_empty_axes() {
+        let single = ShaderPermutations::new("a.hlsl", "")
+            .with_entry_point("main")
+            .with_target_profile("cs_6_5")
+            .with_axis(DefineAxis::enumeration("ONLY", ["X"]))
+            .with_axis(DefineAxis::bool("B"));
+        assert_eq!(
+            keys(&single),
+            ["main@cs_6_5[ONLY=X]", "main@cs_6_5[ONLY=X,B]"]
+        );
+
+        let empty = single.with_axis(DefineAxis::enumeration("NONE", Vec::<String>::new()));
+        assert!(empty.keys().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+//! Parallel compilation of many shaders with a single loaded DXC library.
+
+use crate::utils::{DefaultIncludeHandler, OperationOutput, Result};
+use crate::wrapper::{Dxc, DxcCompiler, DxcLibrary, IncludeHandler};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+
+/// Owned description of a single compilation, the owned counterpart of the arguments to
+/// [`crate::compile_hlsl()`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CompileJob {
+    pub source_name: String,
+    pub source: String,
+    pub entry_point: String,
+    pub target_profile: String,
+    pub args: Vec<String>,
+    pub defines: Vec<(String, Option<String>)>,
+}
+
+impl CompileJob {
+    pub fn new(
+        source_name: impl Into<String>,
+        source: impl Into<String>,
+        entry_point: impl Into<String>,
+        target_profile: impl Into<String>,
+    ) -> Self {
+        Self {
+            source_name: source_name.into(),
+            source: source.into(),
+            entry_point: entry_point.into(),
+            target_profile: target_profile.into(),
+            args: vec![],
+            defines: vec![],
+        }
+    }
+
+    pub fn with_arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn with_args<I: IntoIterator<Item = S>, S: Into<String>>(mut self, args: I) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds a define, where a value of [`None`] defines `name` as `1`.
+    pub fn with_define(mut self, name: impl Into<String>, value: Option<&str>) -> Self {
+        self.defines.push((name.into(), value.map(str::to_owned)));
+        self
+    }
+
+    /// Compiles this job with an existing compiler and library.
+    pub fn compile(
+        &self,
+        compiler: &DxcCompiler,
+        library: &DxcLibrary,
+        include_handler: Option<&mut dyn IncludeHandler>,
+    ) -> Result<OperationOutput> {
+        let blob = library.create_blob_with_encoding_from_str(&self.source)?;
+
+        let args = self.args.iter().map(String::as_str).collect::<Vec<_>>();
+        let defines = self
+            .defines
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+            .collect::<Vec<_>>();
+
+        let result = compiler.compile(
+            &blob,
+            &self.source_name,
+            &self.entry_point,
+            &self.target_profile,
+            &args,
+            include_handler,
+            &defines,
+        )?;
+
+        OperationOutput::from_operation_result(result)
+    }
+}
+
+type IncludeHandlerFactory = dyn Fn(&CompileJob) -> Box<dyn IncludeHandler> + Send + Sync;
+
+/// Compiles batches of [`CompileJob`]s on multiple threads.
+///
+/// The DXC library is loaded once for the lifetime of the pool, and every worker thread creates
+/// its own [`DxcCompiler`] and [`DxcLibrary`] as DXC objects must not be used concurrently.
+///
+/// ```no_run
+/// use hassle_rs::{CompileJob, CompilerPool, Dxc};
+///
+/// let source = include_str!("../examples/copy.hlsl");
+///
+/// let pool = CompilerPool::new(Dxc::new(None)?);
+/// let jobs = ["cs_6_0", "cs_6_5"]
+///     .iter()
+///     .map(|profile| CompileJob::new("copy.hlsl", source, "copyCs", *profile))
+///     .collect::<Vec<_>>();
+///
+/// for (job, result) in jobs.iter().zip(pool.compile(&jobs)) {
+///     println!("{}: {:?}", job.target_profile, result.map(|output| output.blob.len()));
+/// }
+/// # Ok::<(), hassle_rs::HassleError>(())
+/// ```
+pub struct CompilerPool {
+    dxc: Arc<Dxc>,
+    num_threads: NonZeroUsize,
+    include_handler: Arc<IncludeHandlerFactory>,
+}
+
+impl CompilerPool {
+    /// Creates a pool with one worker per available CPU, resolving includes from the
+    /// filesystem like [`crate::compile_hlsl()`].
+    pub fn new(dxc: Dxc) -> Self {
+        Self {
+            dxc: Arc::new(dxc),
+            num_threads: std::thread::available_parallelism()
+                .unwrap_or(NonZeroUsize::new(1).unwrap()),
+            include_handler: Arc::new(|_| Box::new(DefaultIncludeHandler {})),
+        }
+    }
+
+    pub fn with_num_threads(mut self, num_threads: NonZeroUsize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Sets the function that creates a fresh include handler for every job.  It is called on
+    /// the worker thread that compiles the job.
+    pub fn with_include_handler<F, H>(mut self, create_include_handler: F) -> Self
+    where
+        F: Fn(&CompileJob) -> H + Send + Sync + 'static,
+        H: IncludeHandler + 'static,
+    {
+        self.include_handler = Arc::new(move |job| Box::new(create_include_handler(job)));
+        self
+    }
+
+    pub fn dxc(&self) -> &Dxc {
+        &self.dxc
+    }
+
+    pub fn num_threads(&self) -> NonZeroUsize {
+        self.num_threads
+    }
+
+    /// Compiles all `jobs` in parallel, returning their results in submission order.
+    pub fn compile(&self, jobs: &[CompileJob]) -> Vec<Result<OperationOutput>> {
+        let jobs: Arc<[CompileJob]> = jobs.into();
+        let next_job = Arc::new(AtomicUsize::new(0));
+        let num_threads = self.num_threads.get().min(jobs.len());
+        let (sender, receiver) = mpsc::channel();
+
+        let workers = (0..num_threads)
+            .map(|_| {
+                let worker = Worker {
+                    dxc: self.dxc.clone(),
+                    include_handler: self.include_handler.clone(),
+                    jobs: jobs.clone(),
+                    next_job: next_job.clone(),
+                    results: sender.clone(),
+                };
+                std::thread::spawn(move || worker.run())
+            })
+            .collect::<Vec<_>>();
+        // Leave only the workers' senders, so that the receiver ends once they are done
+        drop(sender);
+
+        for worker in workers {
+            if let Err(panic) = worker.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+
+        let mut results = receiver.into_iter().collect::<Vec<_>>();
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+/// State shared with a worker thread of [`CompilerPool::compile()`].
+struct Worker {
+    dxc: Arc<Dxc>,
+    include_handler: Arc<IncludeHandlerFactory>,
+    jobs: Arc<[CompileJob]>,
+    next_job: Arc<AtomicUsize>,
+    results: Sender<(usize, Result<OperationOutput>)>,
+}
+
+impl Worker {
+    fn run(self) {
+        let mut instance: Option<(DxcCompiler, DxcLibrary)> = None;
+
+        loop {
+            let index = self.next_job.fetch_add(1, Ordering::Relaxed);
+            let job = match self.jobs.get(index) {
+                Some(job) => job,
+                None => break,
+            };
+
+            // Creation is retried for the next job if it fails, so that every job gets a result
+            let result = match &instance {
+                Some(instance) => Ok(instance),
+                None => self
+                    .create_instance()
+                    .map(|new_instance| &*instance.insert(new_instance)),
+            }
+            .and_then(|(compiler, library)| {
+                let mut include_handler = (self.include_handler)(job);
+                job.compile(compiler, library, Some(include_handler.as_mut()))
+            });
+
+            // The receiver outlives all workers
+            let _ = self.results.send((index, result));
+        }
+    }
+
+    fn create_instance(&self) -> Result<(DxcCompiler, DxcLibrary)> {
+        Ok((self.dxc.create_compiler()?, self.dxc.create_library()?))
+    }
+}
@@ -1,41 +1,54 @@
 use std::ffi::CStr;
 use std::path::PathBuf;
 
+use crate::diagnostics::{parse_diagnostics, Diagnostic};
+use crate::discovery::LoadAttempt;
+use crate::include::{Dependencies, DependencyTracker};
 use crate::os::{SysFreeString, SysStringLen, BSTR, HRESULT, LPCSTR, LPCWSTR, WCHAR};
 use crate::wrapper::*;
 use thiserror::Error;
 
-pub(crate) fn to_wide(msg: &str) -> Vec<WCHAR> {
+pub(crate) fn to_wide(msg: &str) -> Result<Vec<WCHAR>> {
     widestring::WideCString::from_str(msg)
-        .unwrap()
-        .into_vec_with_nul()
+        .map(widestring::WideCString::into_vec_with_nul)
+        .map_err(|_| HassleError::InteriorNul(msg.to_owned()))
 }
 
-pub(crate) fn from_wide(wide: LPCWSTR) -> String {
+pub(crate) fn from_wide(wide: LPCWSTR) -> Result<String> {
+    if wide.is_null() {
+        return Err(HassleError::NullOutput("string"));
+    }
     unsafe { widestring::WideCStr::from_ptr_str(wide) }
         .to_string()
-        .expect("widestring decode failed")
+        .map_err(|e| HassleError::InvalidString(Box::new(e)))
 }
 
-pub(crate) fn from_bstr(string: BSTR) -> String {
+pub(crate) fn from_bstr(string: BSTR) -> Result<String> {
+    // A null BSTR is a valid representation of the empty string
+    if string.is_null() {
+        return Ok(String::new());
+    }
     let len = unsafe { SysStringLen(string) } as usize;
 
     let result = unsafe { widestring::WideStr::from_ptr(string, len) }
         .to_string()
-        .expect("widestring decode failed");
+        .map_err(|e| HassleError::InvalidString(Box::new(e)));
 
     unsafe { SysFreeString(string) };
     result
 }
 
-pub(crate) fn from_lpstr(string: LPCSTR) -> String {
+pub(crate) fn from_lpstr(string: LPCSTR) -> Result<String> {
+    if string.is_null() {
+        return Err(HassleError::NullOutput("string"));
+    }
     unsafe { CStr::from_ptr(string) }
         .to_str()
-        .unwrap()
-        .to_owned()
+        .map(str::to_owned)
+        .map_err(|e| HassleError::InvalidString(Box::new(e)))
 }
 
-struct DefaultIncludeHandler {}
+pub(crate) struct DefaultIncludeHandler {}
 
 impl DxcIncludeHandler for DefaultIncludeHandler {
     fn load_source(&mut self, filename: String) -> Option<String> {
@@ -56,7 +69,7 @@ impl DxcIncludeHandler for DefaultIncludeHandler {
 pub enum HassleError {
     #[error("Dxc error {0}: {1}")]
     OperationError(HRESULT, String),
-    #[error("Win32 error: {0}")]
+    #[error("Win32 error {0}: {}", .0.description().unwrap_or("unknown error"))]
     Win32Error(HRESULT),
     #[error("Failed to load library {filename:?}: {inner:?}")]
     LoadLibraryError {
@@ -64,12 +77,58 @@ pub enum HassleError {
         #[source]
         inner: libloading::Error,
     },
+    #[error("Failed to find {name}, tried:{}", crate::discovery::format_attempts(.attempts))]
+    LibraryNotFound {
+        name: String,
+        attempts: Vec<LoadAttempt>,
+    },
     #[error("LibLoading error: {0:?}")]
     LibLoadingError(#[from] libloading::Error),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Failed to include {filename:?}: {inner}")]
+    IncludeError {
+        filename: String,
+        #[source]
+        inner: IncludeError,
+    },
+    #[error("Operation returned status {status} with {output_len} bytes of output: {messages}")]
+    InconsistentOutput {
+        status: HRESULT,
+        output_len: usize,
+        messages: String,
+    },
+    #[error("Unsupported code page {0}")]
+    UnsupportedCodePage(u32),
+    #[error("Blob has encoding {0:?} instead of UTF-8, convert it with `DxcLibrary::get_blob_as_utf8()`")]
+    NotUtf8(DxcEncoding),
+    #[error("Blob has encoding {0:?} instead of DXC's wide encoding")]
+    NotWide(DxcEncoding),
+    #[error("DXC returned a malformed string: {0}")]
+    InvalidString(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("String contains an interior nul character: {0:?}")]
+    InteriorNul(String),
+    #[error("DXC returned a null {0}")]
+    NullOutput(&'static str),
+    #[error("Not a well-formed DXIL container")]
+    MalformedContainer,
+    #[error("Blob wraps a read-only buffer and cannot be modified in place")]
+    ReadOnlyBlob,
 }
 
 pub type Result<T, E = HassleError> = std::result::Result<T, E>;
 
+impl HassleError {
+    /// Parses the messages of an [`HassleError::OperationError`] into structured diagnostics,
+    /// see [`parse_diagnostics()`].  Returns an empty list for all other errors.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Self::OperationError(_, messages) => parse_diagnostics(messages),
+            _ => vec![],
+        }
+    }
+}
+
 impl HRESULT {
     /// Turns an [`HRESULT`] from the COM [`crate::ffi`] API declaration
     /// into a [`Result`] containing [`HassleError`].
@@ -109,52 +168,87 @@ pub struct OperationOutput {
 }
 
 impl OperationOutput {
+    /// Parses [`Self::messages`] into structured diagnostics, see [`parse_diagnostics()`].
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.messages
+            .as_deref()
+            .map(parse_diagnostics)
+            .unwrap_or_default()
+    }
+
+    /// Helper to process [`DxcOperationResult`].
+    pub fn from_operation_result(result: DxcOperationResult) -> Result<Self> {
+        OperationBlob::from_operation_result(result).map(Self::from)
+    }
+}
+
+/// Same as [`OperationOutput`], but takes ownership of the blob allocated by DXC instead of
+/// copying it into a [`Vec`].
+#[derive(Debug)]
+pub struct OperationBlob {
+    /// Messages ("error buffer") from DXC, see [`OperationOutput::messages`].
+    pub messages: Option<String>,
+    pub blob: DxcBlob,
+}
+
+impl OperationBlob {
+    /// Parses [`Self::messages`] into structured diagnostics, see [`parse_diagnostics()`].
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.messages
+            .as_deref()
+            .map(parse_diagnostics)
+            .unwrap_or_default()
+    }
+
     /// Helper to process [`DxcOperationResult`].
     pub fn from_operation_result(result: DxcOperationResult) -> Result<Self> {
         // Result blobs are always available, they might just be empty (length == 0) if there's no
         // relevant data in them.
         let error = result.get_error_buffer()?;
-        let error = error.as_str().expect("UTF-8 blob");
+        let error = error.as_str()?;
 
         let output = result.get_result()?;
 
         let status = result.get_status()?;
 
-        if status.is_err() {
-            assert!(
-                output.as_ref().is_empty(),
-                "Compilation unsuccessful but output has {} bytes, status is {}: {}",
-                output.as_ref().len(),
+        // Failed operations should not produce output, and successful ones always do
+        if status.is_err() != output.as_slice().is_empty() {
+            return Err(HassleError::InconsistentOutput {
                 status,
-                error
-            );
+                output_len: output.as_slice().len(),
+                messages: error.to_owned(),
+            });
+        }
+
+        if status.is_err() {
             Err(HassleError::OperationError(status, error.to_owned()))
         } else {
-            assert!(
-                !output.as_ref().is_empty(),
-                "Compilation successful but output is empty, status is {}: {}",
-                status,
-                error
-            );
-            Ok(OperationOutput {
+            Ok(OperationBlob {
                 messages: if error.is_empty() {
                     None
                 } else {
                     Some(error.to_owned())
                 },
-                blob: output.as_slice().to_vec(),
+                blob: output,
             })
         }
     }
 }
 
+impl From<OperationBlob> for OperationOutput {
+    fn from(output: OperationBlob) -> Self {
+        Self {
+            messages: output.messages,
+            blob: output.blob.as_slice().to_vec(),
+        }
+    }
+}
+
 /// Helper function to directly compile a HLSL shader to an intermediate language,
-/// this function expects `dxcompiler.dll` to be available in the current
-/// executable environment.
+/// this function expects `dxcompiler.dll` to be discoverable, see
+/// [`crate::discovery::LibrarySearch::candidates()`].
 ///
 /// Specify -spirv as one of the `args` to compile to SPIR-V
-/// `dxc_path` can point to a library directly or the directory containing the library,
-/// in which case the appended filename depends on the platform.
 pub fn compile_hlsl(
     source_name: &str,
     shader_text: &str,
@@ -183,23 +277,172 @@ pub fn compile_hlsl(
     OperationOutput::from_operation_result(result)
 }
 
+/// Same as [`compile_hlsl()`], but additionally returns every file the compilation read.
+///
+/// `source_name` is recorded as the main source, followed by all includes in the order they were
+/// loaded.  Use [`Dependencies::write_depfile()`] to hand these to Make or Ninja.  The
+/// dependencies are returned even when compilation fails, so that the build is rerun once the
+/// offending file is fixed.
+pub fn compile_hlsl_with_dependencies(
+    source_name: &str,
+    shader_text: &str,
+    entry_point: &str,
+    target_profile: &str,
+    args: &[&str],
+    defines: &[(&str, Option<&str>)],
+) -> (Result<OperationOutput>, Dependencies) {
+    let mut include_handler =
+        DependencyTracker::new(DefaultIncludeHandler {}).with_main_source(source_name);
+
+    let output = (|| {
+        let dxc = Dxc::new(None)?;
+
+        let compiler = dxc.create_compiler()?;
+        let library = dxc.create_library()?;
+
+        let blob = library.create_blob_with_encoding_from_str(shader_text)?;
+
+        let result = compiler.compile(
+            &blob,
+            source_name,
+            entry_point,
+            target_profile,
+            args,
+            Some(&mut include_handler),
+            defines,
+        )?;
+
+        OperationOutput::from_operation_result(result)
+    })();
+
+    let (_, dependencies) = include_handler.into_inner();
+    (output, dependencies)
+}
+
+/// A loaded validator together with the libraries it lives in, which must outlive it.
+struct Validator {
+    validator: DxcValidator,
+    library: DxcLibrary,
+    _dxil: Dxil,
+    _dxc: Dxc,
+}
+
+impl Validator {
+    fn load() -> Result<Self> {
+        let dxc = Dxc::new(None)?;
+        let dxil = Dxil::new_next_to(&dxc)?;
+
+        Ok(Self {
+            validator: dxil.create_validator()?,
+            library: dxc.create_library()?,
+            _dxil: dxil,
+            _dxc: dxc,
+        })
+    }
+
+    fn validate(&self, data: &[u8]) -> Result<OperationOutput> {
+        let blob_encoding = self.library.create_blob_with_encoding(data)?;
+
+        let result = self.validator.validate(&blob_encoding)?;
+
+        OperationOutput::from_operation_result(result)
+    }
+}
+
 /// Helper function to validate a DXIL binary independent from the compilation process,
-/// this function expects `dxcompiler.dll` and `dxil.dll` to be available in the current
-/// execution environment.
+/// this function expects `dxcompiler` and `dxil` to be discoverable, see
+/// [`Dxil::new_next_to()`].
 ///
-/// `dxil.dll` is only available on Windows.
+/// `dxil.dll` ships with the Windows SDK and DXC releases, `libdxil.so` only ships with recent
+/// DXC releases for Linux.  Use [`sign_dxil()`] to fall back to [`fake_sign_dxil_in_place()`]
+/// when it is not available.
 pub fn validate_dxil(data: &[u8]) -> Result<OperationOutput> {
-    let dxc = Dxc::new(None)?;
-    let dxil = Dxil::new(None)?;
+    Validator::load()?.validate(data)
+}
 
-    let validator = dxil.create_validator()?;
-    let library = dxc.create_library()?;
+/// Decides how [`sign_dxil()`] signs a DXIL container.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SigningPolicy {
+    /// Validate with the `dxil` library, and fail if it cannot be loaded.
+    Validate,
+    /// Validate with the `dxil` library if it can be loaded, otherwise fake sign.  This is the
+    /// default.
+    ValidateOrFakeSign,
+    /// Always fake sign, without loading any library.
+    FakeSign,
+}
 
-    let blob_encoding = library.create_blob_with_encoding(data)?;
+impl Default for SigningPolicy {
+    fn default() -> Self {
+        Self::ValidateOrFakeSign
+    }
+}
 
-    let result = validator.validate(&blob_encoding)?;
+/// How [`sign_dxil()`] signed a DXIL container.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SigningMethod {
+    /// Validated and signed by the `dxil` library, see [`validate_dxil()`].
+    Validated,
+    /// Signed without validation, see [`fake_sign_dxil_in_place()`].
+    FakeSigned,
+}
 
-    OperationOutput::from_operation_result(result)
+/// The output of [`sign_dxil()`].
+#[derive(Debug)]
+pub struct SignedDxil {
+    pub method: SigningMethod,
+    /// The signed container, with validator messages if it was validated.
+    pub output: OperationOutput,
+    /// Why the validator could not be loaded when falling back to fake signing under
+    /// [`SigningPolicy::ValidateOrFakeSign`].
+    pub validator_error: Option<HassleError>,
+}
+
+/// Signs a DXIL container so that it can be loaded by D3D12 outside of Developer Mode.
+///
+/// Falling back to fake signing only happens when the validator cannot be loaded: a container
+/// that fails validation is returned as an error under every policy but
+/// [`SigningPolicy::FakeSign`].
+///
+/// ```no_run
+/// use hassle_rs::{sign_dxil, SigningMethod, SigningPolicy};
+///
+/// # let dxil = vec![];
+/// let signed = sign_dxil(&dxil, SigningPolicy::ValidateOrFakeSign)?;
+/// if signed.method == SigningMethod::FakeSigned {
+///     println!("Not validated: {:?}", signed.validator_error);
+/// }
+/// # Ok::<(), hassle_rs::HassleError>(())
+/// ```
+pub fn sign_dxil(data: &[u8], policy: SigningPolicy) -> Result<SignedDxil> {
+    let validator_error = match policy {
+        SigningPolicy::FakeSign => None,
+        SigningPolicy::Validate | SigningPolicy::ValidateOrFakeSign => match Validator::load() {
+            Ok(validator) => {
+                return Ok(SignedDxil {
+                    method: SigningMethod::Validated,
+                    output: validator.validate(data)?,
+                    validator_error: None,
+                })
+            }
+            Err(e) if policy == SigningPolicy::ValidateOrFakeSign => Some(e),
+            Err(e) => return Err(e),
+        },
+    };
+
+    let mut blob = data.to_vec();
+    if !fake_sign_dxil_in_place(&mut blob) {
+        return Err(HassleError::MalformedContainer);
+    }
+
+    Ok(SignedDxil {
+        method: SigningMethod::FakeSigned,
+        output: OperationOutput {
+            messages: None,
+            blob,
+        },
+        validator_error,
+    })
 }
 
 pub use crate::fake_sign::fake_sign_dxil_in_place;
@@ -4,23 +4,37 @@
     clippy::type_complexity
 )]
 
+use crate::discovery::LibrarySearch;
 use crate::ffi::*;
-use crate::os::{HRESULT, LPCWSTR, LPWSTR, WCHAR};
-use crate::utils::{from_wide, to_wide, HassleError, Result};
-use com::{class, interfaces::IUnknown, production::Class, production::ClassAllocation, Interface};
-use libloading::{library_filename, Library, Symbol};
+use crate::os::{CoTaskMemFree, BSTR, HRESULT, LPCWSTR, LPWSTR, WCHAR};
+use crate::utils::{from_bstr, from_lpstr, from_wide, to_wide, HassleError, Result};
+use com::{
+    class, interfaces, interfaces::IUnknown, production::Class, production::ClassAllocation,
+    Interface, IID,
+};
+use libloading::{Library, Symbol};
+use std::alloc::{GlobalAlloc, Layout};
+use std::any::Any;
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::convert::TryFrom;
+use std::convert::{TryFrom, TryInto};
+use std::ffi::c_void;
 use std::fmt;
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::Arc;
+use thiserror::Error;
 
 #[repr(transparent)]
 pub struct DxcBlob {
     inner: IDxcBlob,
 }
 
+// DXC objects are reference counted atomically and may be moved to another thread, but must not
+// be used from multiple threads at the same time.
+unsafe impl Send for DxcBlob {}
+
 impl fmt::Debug for DxcBlob {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("DxcBlob")
@@ -47,16 +61,58 @@ impl DxcBlob {
         }
     }
 
+    /// A blob that wraps a read-only buffer (see [`Self::is_read_only()`]) is first replaced by
+    /// a modifiable copy, use [`Self::try_as_mut_slice()`] to avoid that.
     pub fn as_mut_slice(&mut self) -> &mut [u8] {
-        let len = unsafe { self.inner.get_buffer_size() };
+        if self.is_read_only() {
+            *self = Self::from_owned_mut(self.as_slice().to_vec());
+        }
+        unsafe { self.as_mut_slice_unchecked() }
+    }
+
+    /// Like [`Self::as_mut_slice()`], but returns [`HassleError::ReadOnlyBlob`] instead of
+    /// copying a read-only blob.
+    pub fn try_as_mut_slice(&mut self) -> Result<&mut [u8]> {
+        if self.is_read_only() {
+            return Err(HassleError::ReadOnlyBlob);
+        }
+        Ok(unsafe { self.as_mut_slice_unchecked() })
+    }
+
+    /// # Safety
+    /// The blob must not be read-only.
+    unsafe fn as_mut_slice_unchecked(&mut self) -> &mut [u8] {
+        let len = self.inner.get_buffer_size();
         if len == 0 {
             &mut []
         } else {
-            unsafe { std::slice::from_raw_parts_mut(self.inner.get_buffer_pointer().cast(), len) }
+            std::slice::from_raw_parts_mut(self.inner.get_buffer_pointer().cast(), len)
         }
     }
 }
 
+impl DxcBlob {
+    /// Wraps `data` in a blob without copying it, see [`DxcBlobEncoding::from_owned()`].
+    pub fn from_owned<T: AsRef<[u8]> + Send + 'static>(data: T) -> Self {
+        DxcBlobEncoding::from_owned(data, None).into()
+    }
+
+    /// Wraps `data` in a blob without copying it, like [`Self::from_owned()`].  The contents of
+    /// the returned blob may be modified, such as by [`DxcValidator::validate()`] which writes
+    /// the signature in place.
+    pub fn from_owned_mut<T: AsMut<[u8]> + Send + 'static>(data: T) -> Self {
+        DxcBlobEncoding::from_owned_mut(data, None).into()
+    }
+
+    /// Returns `true` for blobs created by [`Self::from_owned()`] and
+    /// [`DxcBlobEncoding::from_owned()`], whose contents must not be modified.
+    pub fn is_read_only(&self) -> bool {
+        self.inner
+            .query_interface::<IHassleBlob>()
+            .map_or(false, |blob| unsafe { blob.is_read_only() } != 0)
+    }
+}
+
 impl AsRef<[u8]> for DxcBlob {
     fn as_ref(&self) -> &[u8] {
         self.as_slice()
@@ -73,6 +129,82 @@ impl AsMut<[u8]> for DxcBlob {
 pub enum DxcEncoding {
     Unknown,
     Utf8,
+    /// Little-endian UTF-16, see [`CP_UTF16`].
+    Utf16,
+    /// Big-endian UTF-16, see [`CP_UTF16BE`].
+    Utf16Be,
+    /// Little-endian UTF-32, see [`CP_UTF32`].
+    Utf32,
+    /// Big-endian UTF-32, see [`CP_UTF32BE`].
+    Utf32Be,
+    /// Any other Windows code page identifier.
+    CodePage(u32),
+}
+
+impl DxcEncoding {
+    pub fn from_code_page(code_page: u32) -> Self {
+        match code_page {
+            CP_UTF8 => Self::Utf8,
+            CP_UTF16 => Self::Utf16,
+            CP_UTF16BE => Self::Utf16Be,
+            CP_UTF32 => Self::Utf32,
+            CP_UTF32BE => Self::Utf32Be,
+            x => Self::CodePage(x),
+        }
+    }
+
+    /// Returns [`None`] for [`DxcEncoding::Unknown`].
+    pub fn code_page(&self) -> Option<u32> {
+        match *self {
+            Self::Unknown => None,
+            Self::Utf8 => Some(CP_UTF8),
+            Self::Utf16 => Some(CP_UTF16),
+            Self::Utf16Be => Some(CP_UTF16BE),
+            Self::Utf32 => Some(CP_UTF32),
+            Self::Utf32Be => Some(CP_UTF32BE),
+            Self::CodePage(x) => Some(x),
+        }
+    }
+
+    /// Size in bytes of a code unit of the Unicode encodings.
+    fn unit_size(&self) -> Option<usize> {
+        match Self::from_code_page(self.code_page()?) {
+            Self::Utf8 => Some(1),
+            Self::Utf16 | Self::Utf16Be => Some(2),
+            Self::Utf32 | Self::Utf32Be => Some(4),
+            _ => None,
+        }
+    }
+
+    /// Detects the encoding from a leading byte order mark, returning the code page together
+    /// with the size of the byte order mark.
+    ///
+    /// ```
+    /// use hassle_rs::DxcEncoding;
+    ///
+    /// assert_eq!(DxcEncoding::from_bom(b"\xEF\xBB\xBFfloat4"), Some((DxcEncoding::Utf8, 3)));
+    /// assert_eq!(DxcEncoding::from_bom(b"\xFE\xFF\0f"), Some((DxcEncoding::Utf16Be, 2)));
+    /// assert_eq!(DxcEncoding::from_bom(b"float4"), None);
+    /// ```
+    pub fn from_bom(data: &[u8]) -> Option<(Self, usize)> {
+        // UTF-32 must be checked first, as its little-endian BOM starts with that of UTF-16
+        [4, 1, 2]
+            .iter()
+            .find_map(|&unit_size| Self::from_bom_of_size(data, unit_size))
+    }
+
+    /// Like [`Self::from_bom()`], but only recognizes byte order marks of encodings with
+    /// `unit_size`-byte code units.
+    fn from_bom_of_size(data: &[u8], unit_size: usize) -> Option<(Self, usize)> {
+        match (unit_size, data) {
+            (1, [0xEF, 0xBB, 0xBF, ..]) => Some((Self::Utf8, 3)),
+            (2, [0xFF, 0xFE, ..]) => Some((Self::Utf16, 2)),
+            (2, [0xFE, 0xFF, ..]) => Some((Self::Utf16Be, 2)),
+            (4, [0xFF, 0xFE, 0, 0, ..]) => Some((Self::Utf32, 4)),
+            (4, [0, 0, 0xFE, 0xFF, ..]) => Some((Self::Utf32Be, 4)),
+            _ => None,
+        }
+    }
 }
 
 #[repr(transparent)]
@@ -80,11 +212,13 @@ pub struct DxcBlobEncoding {
     inner: IDxcBlobEncoding,
 }
 
+unsafe impl Send for DxcBlobEncoding {}
+
 impl fmt::Debug for DxcBlobEncoding {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("DxcBlobEncoding")
             .field("blob", self.deref())
-            .field("encoding", &self.encoding().unwrap())
+            .field("encoding", &self.encoding())
             .finish()
     }
 }
@@ -94,34 +228,239 @@ impl DxcBlobEncoding {
         Self { inner }
     }
 
-    fn encoding(&self) -> Result<DxcEncoding> {
+    /// The encoding DXC declares for this blob, which is [`DxcEncoding::Unknown`] for binary
+    /// data and text that has yet to be detected from its byte order mark.
+    pub fn encoding(&self) -> Result<DxcEncoding> {
         let mut known = 0;
         let mut code_page = 0;
         unsafe { self.inner.get_encoding(&mut known, &mut code_page) }.result()?;
-        Ok(if known == 0 {
-            assert_eq!(code_page, CP_ACP);
-            DxcEncoding::Unknown
-        } else if known == 1 {
-            match code_page {
-                CP_UTF8 => DxcEncoding::Utf8,
-                x => todo!("Unknown codepage {x:#}"),
-            }
+        if known == 0 {
+            Ok(DxcEncoding::Unknown)
         } else {
-            panic!("Unexpected boolean value {:#}", known)
-        })
+            Ok(DxcEncoding::from_code_page(code_page))
+        }
+    }
+
+    /// Returns [`HassleError::NotUtf8`] if [`Self::encoding()`] is not [`DxcEncoding::Utf8`].
+    /// Call [`DxcLibrary::get_blob_as_utf8()`] to convert it, and call this function on the
+    /// resulting blob again.  A leading byte order mark is skipped.
+    pub fn as_str(&self) -> Result<&str> {
+        match self.encoding()? {
+            DxcEncoding::Utf8 => {
+                let data = self.as_slice();
+                let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+                std::str::from_utf8(data).map_err(|e| HassleError::InvalidString(Box::new(e)))
+            }
+            encoding => Err(HassleError::NotUtf8(encoding)),
+        }
     }
 
-    /// Returns [`None`] if [`Self::encoding()`] is not [`DxcEncoding::Utf8`].  Call
-    /// [`DxcLibrary::get_blob_as_utf8()`] to convert it, and call this function on the resulting
-    /// blob again.
-    pub fn as_str(&self) -> Option<&str> {
-        match self.encoding().expect("Known encoding") {
-            DxcEncoding::Unknown => None,
-            DxcEncoding::Utf8 => Some(
-                std::str::from_utf8(self.as_slice())
-                    .expect("DXC claimed the buffer should contain UTF-8"),
+    /// Decodes UTF-8, UTF-16 and UTF-32 text of either endianness, without the byte order mark
+    /// and the nul terminator DXC appends to text it returns.
+    ///
+    /// Blobs with an unknown encoding are decoded according to their byte order mark, or as
+    /// UTF-8 without one.  Other code pages return [`HassleError::UnsupportedCodePage`], convert
+    /// those with [`DxcLibrary::get_blob_as_utf8()`] first.
+    pub fn decode(&self) -> Result<Cow<'_, str>> {
+        let data = self.as_slice();
+        let declared = self.encoding()?;
+        // Only trust a byte order mark of the width of the declared encoding, as the declared
+        // code page may not reflect the endianness of the data
+        let bom = match declared {
+            DxcEncoding::Unknown => DxcEncoding::from_bom(data),
+            encoding => encoding
+                .unit_size()
+                .and_then(|unit_size| DxcEncoding::from_bom_of_size(data, unit_size)),
+        };
+        let (encoding, data) = match (declared, bom) {
+            (_, Some((encoding, bom_len))) => (encoding, &data[bom_len..]),
+            (DxcEncoding::Unknown, None) => (DxcEncoding::Utf8, data),
+            (encoding, None) => (encoding, data),
+        };
+
+        let text = match encoding {
+            DxcEncoding::Utf8 => Cow::Borrowed(
+                std::str::from_utf8(data).map_err(|e| HassleError::InvalidString(Box::new(e)))?,
             ),
+            DxcEncoding::Utf16 | DxcEncoding::Utf16Be => {
+                let units = Self::code_units(
+                    data,
+                    encoding == DxcEncoding::Utf16Be,
+                    u16::from_le_bytes,
+                    u16::from_be_bytes,
+                )?;
+                Cow::Owned(
+                    String::from_utf16(&units)
+                        .map_err(|e| HassleError::InvalidString(Box::new(e)))?,
+                )
+            }
+            DxcEncoding::Utf32 | DxcEncoding::Utf32Be => {
+                let units = Self::code_units(
+                    data,
+                    encoding == DxcEncoding::Utf32Be,
+                    u32::from_le_bytes,
+                    u32::from_be_bytes,
+                )?;
+                Cow::Owned(
+                    units
+                        .into_iter()
+                        .map(char::try_from)
+                        .collect::<Result<String, _>>()
+                        .map_err(|e| HassleError::InvalidString(Box::new(e)))?,
+                )
+            }
+            encoding => {
+                return Err(HassleError::UnsupportedCodePage(
+                    encoding.code_page().unwrap_or(0),
+                ))
+            }
+        };
+
+        Ok(match text {
+            Cow::Borrowed(text) => Cow::Borrowed(text.strip_suffix('\0').unwrap_or(text)),
+            Cow::Owned(mut text) => {
+                if text.ends_with('\0') {
+                    text.pop();
+                }
+                Cow::Owned(text)
+            }
+        })
+    }
+
+    fn code_units<T, const N: usize>(
+        data: &[u8],
+        big_endian: bool,
+        from_le_bytes: fn([u8; N]) -> T,
+        from_be_bytes: fn([u8; N]) -> T,
+    ) -> Result<Vec<T>> {
+        let chunks = data.chunks_exact(N);
+        if !chunks.remainder().is_empty() {
+            return Err(HassleError::InvalidString(
+                format!(
+                    "Length {} is not a multiple of the {}-byte code unit",
+                    data.len(),
+                    N
+                )
+                .into(),
+            ));
+        }
+        let from_bytes = if big_endian {
+            from_be_bytes
+        } else {
+            from_le_bytes
+        };
+        Ok(chunks
+            .map(|chunk| from_bytes(chunk.try_into().unwrap()))
+            .collect())
+    }
+}
+
+interfaces! {
+    // Private interface to recognize blobs implemented by `DxcBlobWrapper`
+    #[uuid("c620a6d0-628e-4eb0-872e-a29ee700eaf6")]
+    unsafe interface IHassleBlob: IUnknown {
+        fn is_read_only(&self) -> i32;
+    }
+}
+
+class! {
+    #[no_class_factory]
+    class DxcBlobWrapper: IDxcBlobEncoding(IDxcBlob), IHassleBlob {
+        // Owns the memory `data` points into, which must not move while the blob lives.
+        _owner: Box<dyn Any + Send>,
+        data: *mut c_void,
+        len: usize,
+        read_only: bool,
+        // Code page when the contents are text, or `None` for binary data
+        code_page: Option<u32>,
+    }
+
+    impl IDxcBlobEncoding for DxcBlobWrapper {
+        fn get_encoding(&self, known: *mut u32, code_page: *mut u32) -> HRESULT {
+            if known.is_null() || code_page.is_null() {
+                return HRESULT(com::sys::E_POINTER);
+            }
+            unsafe {
+                *known = self.code_page.is_some() as u32;
+                *code_page = self.code_page.unwrap_or(0);
+            }
+            HRESULT(0) // NOERROR
+        }
+    }
+
+    impl IDxcBlob for DxcBlobWrapper {
+        fn get_buffer_pointer(&self) -> *mut c_void {
+            self.data
         }
+
+        fn get_buffer_size(&self) -> usize {
+            self.len
+        }
+    }
+
+    impl IHassleBlob for DxcBlobWrapper {
+        fn is_read_only(&self) -> i32 {
+            self.read_only as i32
+        }
+    }
+}
+
+impl DxcBlobEncoding {
+    /// Wraps `data` in a blob without copying it.  The blob takes ownership of `data` and drops
+    /// it once DXC releases its last reference, which may be after the call it was passed to.
+    ///
+    /// `data` is typically a [`Vec<u8>`], [`Arc<[u8]>`](Arc) or memory-mapped file, and must
+    /// return the same slice every time it is dereferenced.  Pass [`CP_UTF8`] as `code_page`
+    /// for shader source, or [`None`] for binary data such as DXIL.
+    ///
+    /// The contents of the blob cannot be modified, see [`DxcBlob::is_read_only()`].
+    ///
+    /// ```no_run
+    /// use hassle_rs::{Dxc, DxcBlob, DxcBlobEncoding, CP_UTF8};
+    /// use std::sync::Arc;
+    ///
+    /// let dxc = Dxc::new(None)?;
+    /// let compiler = dxc.create_compiler()?;
+    ///
+    /// let source: Arc<[u8]> = std::fs::read("shader.hlsl")?.into();
+    /// let blob = DxcBlobEncoding::from_owned(source.clone(), Some(CP_UTF8));
+    /// let result = compiler.compile(&blob, "shader.hlsl", "main", "ps_6_5", &[], None, &[])?;
+    ///
+    /// // Validation signs the shader in place, so it needs a mutable buffer
+    /// let dxil = DxcBlob::from_owned_mut(result.get_result()?.as_slice().to_vec());
+    /// # Ok::<(), hassle_rs::HassleError>(())
+    /// ```
+    pub fn from_owned<T: AsRef<[u8]> + Send + 'static>(data: T, code_page: Option<u32>) -> Self {
+        // Take the pointer after boxing, as inline data such as `[u8; N]` moves into the box
+        let owner = Box::new(data);
+        let slice = (*owner).as_ref();
+        let (ptr, len) = (slice.as_ptr() as *mut c_void, slice.len());
+        Self::wrap(owner, ptr, len, true, code_page)
+    }
+
+    /// Wraps `data` in a blob without copying it, like [`Self::from_owned()`], but allows its
+    /// contents to be modified.
+    pub fn from_owned_mut<T: AsMut<[u8]> + Send + 'static>(
+        data: T,
+        code_page: Option<u32>,
+    ) -> Self {
+        // Take the pointer after boxing, as inline data such as `[u8; N]` moves into the box
+        let mut owner = Box::new(data);
+        let slice = (*owner).as_mut();
+        let (ptr, len) = (slice.as_mut_ptr().cast(), slice.len());
+        Self::wrap(owner, ptr, len, false, code_page)
+    }
+
+    fn wrap(
+        owner: Box<dyn Any + Send>,
+        data: *mut c_void,
+        len: usize,
+        read_only: bool,
+        code_page: Option<u32>,
+    ) -> Self {
+        let wrapper = DxcBlobWrapper::allocate(owner, data, len, read_only, code_page);
+        // Infallible conversion, unlike `query_interface()`
+        Self::new(IDxcBlobEncoding::from(&**wrapper))
     }
 }
 
@@ -155,6 +494,8 @@ pub struct DxcOperationResult {
     inner: IDxcOperationResult,
 }
 
+unsafe impl Send for DxcOperationResult {}
+
 impl DxcOperationResult {
     fn new(inner: IDxcOperationResult) -> Self {
         Self { inner }
@@ -168,14 +509,18 @@ impl DxcOperationResult {
     pub fn get_result(&self) -> Result<DxcBlob> {
         let mut blob = None;
         unsafe { self.inner.get_result(&mut blob) }.result()?;
-        Ok(DxcBlob::new(blob.unwrap()))
+        Ok(DxcBlob::new(
+            blob.ok_or(HassleError::NullOutput("result blob"))?,
+        ))
     }
 
     pub fn get_error_buffer(&self) -> Result<DxcBlobEncoding> {
         let mut blob = None;
 
         unsafe { self.inner.get_error_buffer(&mut blob) }.result()?;
-        Ok(DxcBlobEncoding::new(blob.unwrap()))
+        Ok(DxcBlobEncoding::new(
+            blob.ok_or(HassleError::NullOutput("error buffer"))?,
+        ))
     }
 }
 
@@ -183,6 +528,108 @@ pub trait DxcIncludeHandler {
     fn load_source(&mut self, filename: String) -> Option<String>;
 }
 
+/// Describes a single include lookup performed by DXC, see [`IncludeHandler::load()`].
+#[derive(Clone, Copy, Debug)]
+pub struct IncludeRequest<'a> {
+    /// Filename exactly as DXC requested it.  DXC typically prefixes the directory of the
+    /// including file (relative to the main source) and may mix `/` and `\\` separators.
+    pub name: &'a str,
+    /// Names of the includes that were successfully loaded earlier in this compilation, in
+    /// order.
+    pub loaded: &'a [String],
+}
+
+impl<'a> IncludeRequest<'a> {
+    /// Best-effort guess of the file containing the `#include` directive for this request.
+    ///
+    /// DXC does not pass this information to include handlers.  The guess is the most recently
+    /// loaded include that lives in the directory DXC prefixed to [`Self::name`], or [`None`]
+    /// when no such include exists, in which case the includer is most likely the main source.
+    pub fn includer(&self) -> Option<&'a str> {
+        let parent = |name: &str| {
+            crate::include::normalize_path(name)
+                .rsplit_once('/')
+                .map(|(parent, _)| parent.to_owned())
+        };
+        let requested_parent = parent(self.name)?;
+        self.loaded
+            .iter()
+            .rev()
+            .find(|loaded| parent(loaded).as_ref() == Some(&requested_parent))
+            .map(String::as_str)
+    }
+}
+
+/// Reasons for an [`IncludeHandler`] to reject an include request.
+#[derive(Error, Debug)]
+pub enum IncludeError {
+    /// The file does not exist.  DXC may probe multiple locations for a single `#include`, so
+    /// this error is not surfaced unless the compilation fails for other reasons.
+    #[error("File not found")]
+    NotFound,
+    #[error("Permission denied")]
+    PermissionDenied,
+    #[error("Path {0:?} is outside of the include sandbox")]
+    OutsideSandbox(String),
+    #[error("I/O error: {0}")]
+    Io(std::io::Error),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl IncludeError {
+    /// The [`HRESULT`] that is reported back to DXC for this error.
+    pub fn hresult(&self) -> HRESULT {
+        HRESULT(match self {
+            Self::NotFound => -2_147_024_894, // ERROR_FILE_NOT_FOUND / 0x80070002
+            Self::PermissionDenied => -2_147_024_891, // E_ACCESSDENIED / 0x80070005
+            Self::OutsideSandbox(_) => -2_147_024_891, // E_ACCESSDENIED / 0x80070005
+            Self::Io(_) | Self::Other(_) => -2_147_467_259, // E_FAIL / 0x80004005
+        })
+    }
+}
+
+impl From<std::io::Error> for IncludeError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound,
+            std::io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            _ => Self::Io(e),
+        }
+    }
+}
+
+/// Fallible, byte-oriented counterpart of [`DxcIncludeHandler`].
+///
+/// Include contents that are valid UTF-8 are passed to DXC as such, any other content is passed
+/// with an unknown encoding so that DXC can detect it from its byte order mark.
+///
+/// Errors other than [`IncludeError::NotFound`] are reported through
+/// [`HassleError::IncludeError`] when the compilation fails.  Panics are caught before they reach
+/// DXC and are reported the same way.
+///
+/// Every [`DxcIncludeHandler`] implements this trait as well.
+pub trait IncludeHandler {
+    fn load(&mut self, request: &IncludeRequest<'_>) -> Result<Vec<u8>, IncludeError>;
+
+    /// The file on disk that a successful [`Self::load()`] of `request` read, or [`None`] if the
+    /// include did not come from a file.  Used by [`crate::include::DependencyTracker`].
+    ///
+    /// Defaults to [`IncludeRequest::name`], which is the file that handlers opening the
+    /// requested name as-is read.
+    fn resolved_path(&self, request: &IncludeRequest<'_>) -> Option<PathBuf> {
+        Some(PathBuf::from(request.name))
+    }
+}
+
+impl<T: DxcIncludeHandler + ?Sized> IncludeHandler for T {
+    fn load(&mut self, request: &IncludeRequest<'_>) -> Result<Vec<u8>, IncludeError> {
+        self.load_source(request.name.to_owned())
+            .map(String::into_bytes)
+            .ok_or(IncludeError::NotFound)
+    }
+}
+
 class! {
     #[no_class_factory]
     class DxcIncludeHandlerWrapper: IDxcIncludeHandler {
@@ -195,36 +642,70 @@ class! {
         // class object is discarded at the end of our function call.
 
         library: &'static DxcLibrary,
-        handler: RefCell<&'static mut dyn DxcIncludeHandler>,
+        handler: RefCell<&'static mut dyn IncludeHandler>,
 
-        pinned: RefCell<Vec<Pin<String>>>,
+        pinned: RefCell<Vec<Pin<Vec<u8>>>>,
+        loaded: RefCell<Vec<String>>,
+        // First error, other than `IncludeError::NotFound`, returned by the handler
+        error: RefCell<Option<(String, IncludeError)>>,
     }
 
     impl IDxcIncludeHandler for DxcIncludeHandlerWrapper {
         fn load_source(&self, filename: LPCWSTR, include_source: *mut Option<IDxcBlob>) -> HRESULT {
-            let filename = crate::utils::from_wide(filename);
-
-            let mut handler = self.handler.borrow_mut();
-            let source = handler.load_source(filename);
-
-            if let Some(source) = source {
-                let source = Pin::new(source);
-                let blob = self.library
-                    .create_blob_with_encoding_from_str(&source)
-                    .unwrap();
-
-                // unsafe { *include_source = Some(DxcBlob::from(blob).inner) };
-                // TODO: We shouldn't need a clone here, if we could freely upcast by moving.
-                unsafe { *include_source = Some(blob.deref().inner.clone()) };
-                self.pinned.borrow_mut().push(source);
-
-                // NOERROR
-                0
-            } else {
-                -2_147_024_894 // ERROR_FILE_NOT_FOUND / 0x80070002
+            if filename.is_null() || include_source.is_null() {
+                return HRESULT(com::sys::E_POINTER);
             }
-            .into()
+
+            // Never unwind into DXC
+            let filename =
+                unsafe { widestring::WideCStr::from_ptr_str(filename) }.to_string_lossy();
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                self.load_blob(&filename)
+            }))
+            .unwrap_or_else(|_| Err(IncludeError::Other("Include handler panicked".to_owned())));
+
+            match result {
+                Ok(blob) => {
+                    unsafe { *include_source = Some(blob) };
+                    self.loaded.borrow_mut().push(filename);
+                    HRESULT(0) // NOERROR
+                }
+                Err(e) => {
+                    let hr = e.hresult();
+                    if !matches!(e, IncludeError::NotFound) {
+                        self.error.borrow_mut().get_or_insert((filename, e));
+                    }
+                    hr
+                }
+            }
+        }
+    }
+}
+
+impl DxcIncludeHandlerWrapper {
+    fn load_blob(&self, filename: &str) -> Result<IDxcBlob, IncludeError> {
+        let source = {
+            let loaded = self.loaded.borrow();
+            let request = IncludeRequest {
+                name: filename,
+                loaded: &loaded,
+            };
+            self.handler.borrow_mut().load(&request)?
+        };
+
+        let source = Pin::new(source);
+        let blob = if std::str::from_utf8(&source).is_ok() {
+            self.library
+                .create_blob_with_encoding_and_code_page(&source, CP_UTF8)
+        } else {
+            self.library.create_blob_with_encoding(&source)
         }
+        .map_err(|e| IncludeError::Other(e.to_string()))?;
+        self.pinned.borrow_mut().push(source);
+
+        // unsafe { *include_source = Some(DxcBlob::from(blob).inner) };
+        // TODO: We shouldn't need a clone here, if we could freely upcast by moving.
+        Ok(blob.deref().inner.clone())
     }
 }
 
@@ -284,22 +765,47 @@ impl DxcIncludeHandlerWrapper {
     /// of either `library` nor `include_handler`
     unsafe fn create_include_handler(
         library: &'_ DxcLibrary,
-        include_handler: &'_ mut dyn DxcIncludeHandler,
+        include_handler: &'_ mut dyn IncludeHandler,
     ) -> LocalClassAllocation<DxcIncludeHandlerWrapper> {
         #[allow(clippy::missing_transmute_annotations)]
         LocalClassAllocation::new(Self::allocate(
             std::mem::transmute(library),
             RefCell::new(std::mem::transmute(include_handler)),
             RefCell::new(vec![]),
+            RefCell::new(vec![]),
+            RefCell::new(None),
         ))
     }
+
+    /// Turns a failed compilation into [`HassleError::IncludeError`] if the include handler
+    /// rejected a request with anything other than [`IncludeError::NotFound`].
+    fn check_error(
+        include_handler: &Option<LocalClassAllocation<Self>>,
+        result: &DxcOperationResult,
+    ) -> Result<()> {
+        let include_handler = match include_handler {
+            Some(include_handler) => include_handler,
+            None => return Ok(()),
+        };
+        if !result.get_status()?.is_err() {
+            return Ok(());
+        }
+        match include_handler.error.borrow_mut().take() {
+            Some((filename, inner)) => Err(HassleError::IncludeError { filename, inner }),
+            None => Ok(()),
+        }
+    }
 }
 
+pub type DxcCompilerVersion = (u32, u32);
+
 pub struct DxcCompiler {
     inner: IDxcCompiler2,
     library: DxcLibrary,
 }
 
+unsafe impl Send for DxcCompiler {}
+
 impl DxcCompiler {
     fn new(inner: IDxcCompiler2, library: DxcLibrary) -> Self {
         Self { inner, library }
@@ -309,13 +815,9 @@ impl DxcCompiler {
         defines: &[(&str, Option<&str>)],
         wide_defines: &mut Vec<(Vec<WCHAR>, Vec<WCHAR>)>,
         dxc_defines: &mut Vec<DxcDefine>,
-    ) {
+    ) -> Result<()> {
         for (name, value) in defines {
-            if value.is_none() {
-                wide_defines.push((to_wide(name), to_wide("1")));
-            } else {
-                wide_defines.push((to_wide(name), to_wide(value.unwrap())));
-            }
+            wide_defines.push((to_wide(name)?, to_wide(value.unwrap_or("1"))?));
         }
 
         for (ref name, ref value) in wide_defines {
@@ -324,16 +826,22 @@ impl DxcCompiler {
                 value: value.as_ptr(),
             });
         }
+        Ok(())
     }
 
-    fn prep_args(args: &[&str], wide_args: &mut Vec<Vec<WCHAR>>, dxc_args: &mut Vec<LPCWSTR>) {
+    fn prep_args(
+        args: &[&str],
+        wide_args: &mut Vec<Vec<WCHAR>>,
+        dxc_args: &mut Vec<LPCWSTR>,
+    ) -> Result<()> {
         for a in args {
-            wide_args.push(to_wide(a));
+            wide_args.push(to_wide(a)?);
         }
 
         for a in wide_args {
             dxc_args.push(a.as_ptr());
         }
+        Ok(())
     }
 
     pub fn compile(
@@ -343,34 +851,42 @@ impl DxcCompiler {
         entry_point: &str,
         target_profile: &str,
         args: &[&str],
-        include_handler: Option<&mut dyn DxcIncludeHandler>,
+        include_handler: Option<&mut dyn IncludeHandler>,
         defines: &[(&str, Option<&str>)],
         // ) -> Result<DxcBlob, (HRESULT, DxcBlob)> {
     ) -> Result<DxcOperationResult, HassleError> {
         let mut wide_args = vec![];
         let mut dxc_args = vec![];
-        Self::prep_args(args, &mut wide_args, &mut dxc_args);
+        Self::prep_args(args, &mut wide_args, &mut dxc_args)?;
 
         let mut wide_defines = vec![];
         let mut dxc_defines = vec![];
-        Self::prep_defines(defines, &mut wide_defines, &mut dxc_defines);
+        Self::prep_defines(defines, &mut wide_defines, &mut dxc_defines)?;
 
         // Keep alive on the stack
-        let include_handler = include_handler.map(|include_handler| unsafe {
+        let include_handler_wrapper = include_handler.map(|include_handler| unsafe {
             DxcIncludeHandlerWrapper::create_include_handler(&self.library, include_handler)
         });
         // TODO: query_interface() should have a borrow on LocalClassAllocation to prevent things going kaboom
-        let include_handler = include_handler
+        let include_handler = include_handler_wrapper
             .as_ref()
-            .map(|i| i.query_interface().unwrap());
+            .map(|i| {
+                i.query_interface()
+                    .ok_or(HassleError::NullOutput("IDxcIncludeHandler"))
+            })
+            .transpose()?;
+
+        let source_name = to_wide(source_name)?;
+        let entry_point = to_wide(entry_point)?;
+        let target_profile = to_wide(target_profile)?;
 
         let mut result = None;
         unsafe {
             self.inner.compile(
                 &blob.inner,
-                to_wide(source_name).as_ptr(),
-                to_wide(entry_point).as_ptr(),
-                to_wide(target_profile).as_ptr(),
+                source_name.as_ptr(),
+                entry_point.as_ptr(),
+                target_profile.as_ptr(),
                 dxc_args.as_ptr(),
                 dxc_args.len() as u32,
                 dxc_defines.as_ptr(),
@@ -381,8 +897,10 @@ impl DxcCompiler {
         }
         .result()?;
 
-        let result = result.expect("Non-null IDxcOperationResult");
-        Ok(DxcOperationResult::new(result))
+        let result =
+            DxcOperationResult::new(result.ok_or(HassleError::NullOutput("IDxcOperationResult"))?);
+        DxcIncludeHandlerWrapper::check_error(&include_handler_wrapper, &result)?;
+        Ok(result)
     }
 
     pub fn compile_with_debug(
@@ -392,24 +910,32 @@ impl DxcCompiler {
         entry_point: &str,
         target_profile: &str,
         args: &[&str],
-        include_handler: Option<&mut dyn DxcIncludeHandler>,
+        include_handler: Option<&mut dyn IncludeHandler>,
         defines: &[(&str, Option<&str>)],
     ) -> Result<(DxcOperationResult, (String, DxcBlob)), HassleError> {
         let mut wide_args = vec![];
         let mut dxc_args = vec![];
-        Self::prep_args(args, &mut wide_args, &mut dxc_args);
+        Self::prep_args(args, &mut wide_args, &mut dxc_args)?;
 
         let mut wide_defines = vec![];
         let mut dxc_defines = vec![];
-        Self::prep_defines(defines, &mut wide_defines, &mut dxc_defines);
+        Self::prep_defines(defines, &mut wide_defines, &mut dxc_defines)?;
 
         // Keep alive on the stack
-        let include_handler = include_handler.map(|include_handler| unsafe {
+        let include_handler_wrapper = include_handler.map(|include_handler| unsafe {
             DxcIncludeHandlerWrapper::create_include_handler(&self.library, include_handler)
         });
-        let include_handler = include_handler
+        let include_handler = include_handler_wrapper
             .as_ref()
-            .map(|i| i.query_interface().unwrap());
+            .map(|i| {
+                i.query_interface()
+                    .ok_or(HassleError::NullOutput("IDxcIncludeHandler"))
+            })
+            .transpose()?;
+
+        let source_name = to_wide(source_name)?;
+        let entry_point = to_wide(entry_point)?;
+        let target_profile = to_wide(target_profile)?;
 
         let mut result = None;
         let mut debug_blob = None;
@@ -418,9 +944,9 @@ impl DxcCompiler {
         unsafe {
             self.inner.compile_with_debug(
                 &blob.inner,
-                to_wide(source_name).as_ptr(),
-                to_wide(entry_point).as_ptr(),
-                to_wide(target_profile).as_ptr(),
+                source_name.as_ptr(),
+                entry_point.as_ptr(),
+                target_profile.as_ptr(),
                 dxc_args.as_ptr(),
                 dxc_args.len() as u32,
                 dxc_defines.as_ptr(),
@@ -433,11 +959,13 @@ impl DxcCompiler {
         }
         .result()?;
 
-        let result = result.expect("Non-null IDxcOperationResult");
-        let debug_blob = debug_blob.expect("Non-null debug blob");
+        let result =
+            DxcOperationResult::new(result.ok_or(HassleError::NullOutput("IDxcOperationResult"))?);
+        DxcIncludeHandlerWrapper::check_error(&include_handler_wrapper, &result)?;
+        let debug_blob = debug_blob.ok_or(HassleError::NullOutput("debug blob"))?;
         Ok((
-            DxcOperationResult::new(result),
-            (from_wide(debug_filename), DxcBlob::new(debug_blob)),
+            result,
+            (from_wide(debug_filename)?, DxcBlob::new(debug_blob)),
         ))
     }
 
@@ -446,30 +974,36 @@ impl DxcCompiler {
         blob: &DxcBlobEncoding,
         source_name: &str,
         args: &[&str],
-        include_handler: Option<&mut dyn DxcIncludeHandler>,
+        include_handler: Option<&mut dyn IncludeHandler>,
         defines: &[(&str, Option<&str>)],
     ) -> Result<DxcOperationResult, HassleError> {
         let mut wide_args = vec![];
         let mut dxc_args = vec![];
-        Self::prep_args(args, &mut wide_args, &mut dxc_args);
+        Self::prep_args(args, &mut wide_args, &mut dxc_args)?;
 
         let mut wide_defines = vec![];
         let mut dxc_defines = vec![];
-        Self::prep_defines(defines, &mut wide_defines, &mut dxc_defines);
+        Self::prep_defines(defines, &mut wide_defines, &mut dxc_defines)?;
 
         // Keep alive on the stack
-        let include_handler = include_handler.map(|include_handler| unsafe {
+        let include_handler_wrapper = include_handler.map(|include_handler| unsafe {
             DxcIncludeHandlerWrapper::create_include_handler(&self.library, include_handler)
         });
-        let include_handler = include_handler
+        let include_handler = include_handler_wrapper
             .as_ref()
-            .map(|i| i.query_interface().unwrap());
+            .map(|i| {
+                i.query_interface()
+                    .ok_or(HassleError::NullOutput("IDxcIncludeHandler"))
+            })
+            .transpose()?;
+
+        let source_name = to_wide(source_name)?;
 
         let mut result = None;
         unsafe {
             self.inner.preprocess(
                 &blob.inner,
-                to_wide(source_name).as_ptr(),
+                source_name.as_ptr(),
                 dxc_args.as_ptr(),
                 dxc_args.len() as u32,
                 dxc_defines.as_ptr(),
@@ -480,14 +1014,52 @@ impl DxcCompiler {
         }
         .result()?;
 
-        let result = result.expect("Non-null IDxcOperationResult");
-        Ok(DxcOperationResult::new(result))
+        let result =
+            DxcOperationResult::new(result.ok_or(HassleError::NullOutput("IDxcOperationResult"))?);
+        DxcIncludeHandlerWrapper::check_error(&include_handler_wrapper, &result)?;
+        Ok(result)
     }
 
     pub fn disassemble(&self, blob: &DxcBlob) -> Result<DxcBlobEncoding> {
         let mut result_blob = None;
         unsafe { self.inner.disassemble(&blob.inner, &mut result_blob) }.result()?;
-        Ok(DxcBlobEncoding::new(result_blob.unwrap()))
+        Ok(DxcBlobEncoding::new(
+            result_blob.ok_or(HassleError::NullOutput("disassembly blob"))?,
+        ))
+    }
+
+    pub fn version(&self) -> Result<DxcCompilerVersion> {
+        let version = self
+            .inner
+            .query_interface::<IDxcVersionInfo>()
+            .ok_or(HassleError::Win32Error(HRESULT(com::sys::E_NOINTERFACE)))?;
+
+        let mut major = 0;
+        let mut minor = 0;
+
+        unsafe { version.get_version(&mut major, &mut minor) }.result_with_success((major, minor))
+    }
+
+    /// Returns the number of commits and the commit hash this compiler was built from.
+    pub fn commit_info(&self) -> Result<(u32, String)> {
+        let version = self
+            .inner
+            .query_interface::<IDxcVersionInfo2>()
+            .ok_or(HassleError::Win32Error(HRESULT(com::sys::E_NOINTERFACE)))?;
+
+        let mut commit_count = 0;
+        let mut commit_hash: *mut u8 = std::ptr::null_mut();
+
+        unsafe { version.get_commit_info(&mut commit_count, &mut commit_hash) }.result()?;
+
+        let hash = if commit_hash.is_null() {
+            String::new()
+        } else {
+            let hash = crate::utils::from_lpstr(commit_hash.cast());
+            unsafe { CoTaskMemFree(commit_hash.cast()) };
+            hash?
+        };
+        Ok((commit_count, hash))
     }
 }
 
@@ -496,6 +1068,8 @@ pub struct DxcLibrary {
     inner: IDxcLibrary,
 }
 
+unsafe impl Send for DxcLibrary {}
+
 impl DxcLibrary {
     fn new(inner: IDxcLibrary) -> Self {
         Self { inner }
@@ -513,7 +1087,32 @@ impl DxcLibrary {
             )
         }
         .result()?;
-        Ok(DxcBlobEncoding::new(blob.unwrap()))
+        Ok(DxcBlobEncoding::new(
+            blob.ok_or(HassleError::NullOutput("blob"))?,
+        ))
+    }
+
+    /// Creates a blob of text in `code_page`, such as [`CP_UTF16`].  Pass [`CP_ACP`] to let DXC
+    /// detect the encoding of source files from their byte order mark.
+    pub fn create_blob_with_encoding_and_code_page(
+        &self,
+        data: &[u8],
+        code_page: u32,
+    ) -> Result<DxcBlobEncoding> {
+        let mut blob = None;
+
+        unsafe {
+            self.inner.create_blob_with_encoding_from_pinned(
+                data.as_ptr().cast(),
+                data.len() as u32,
+                code_page,
+                &mut blob,
+            )
+        }
+        .result()?;
+        Ok(DxcBlobEncoding::new(
+            blob.ok_or(HassleError::NullOutput("blob"))?,
+        ))
     }
 
     pub fn create_blob_with_encoding_from_str(&self, text: &str) -> Result<DxcBlobEncoding> {
@@ -528,7 +1127,69 @@ impl DxcLibrary {
             )
         }
         .result()?;
-        Ok(DxcBlobEncoding::new(blob.unwrap()))
+        Ok(DxcBlobEncoding::new(
+            blob.ok_or(HassleError::NullOutput("blob"))?,
+        ))
+    }
+
+    /// Reads a file into a blob.  `code_page` declares the encoding of text files, otherwise
+    /// DXC detects it from the byte order mark.
+    pub fn create_blob_from_file(
+        &self,
+        filename: &str,
+        code_page: Option<u32>,
+    ) -> Result<DxcBlobEncoding> {
+        let filename = to_wide(filename)?;
+        let mut blob = None;
+
+        unsafe {
+            self.inner.create_blob_from_file(
+                filename.as_ptr(),
+                code_page
+                    .as_ref()
+                    .map_or(std::ptr::null(), |code_page| code_page),
+                &mut blob,
+            )
+        }
+        .result()?;
+        Ok(DxcBlobEncoding::new(
+            blob.ok_or(HassleError::NullOutput("file blob"))?,
+        ))
+    }
+
+    /// Creates a view of `length` bytes of `blob` starting at `offset`, without copying.  The
+    /// new blob keeps `blob` alive.
+    pub fn create_blob_from_blob(
+        &self,
+        blob: &DxcBlob,
+        offset: u32,
+        length: u32,
+    ) -> Result<DxcBlob> {
+        let mut result_blob = None;
+
+        unsafe {
+            self.inner
+                .create_blob_from_blob(&blob.inner, offset, length, &mut result_blob)
+        }
+        .result()?;
+        Ok(DxcBlob::new(
+            result_blob.ok_or(HassleError::NullOutput("sub-blob"))?,
+        ))
+    }
+
+    /// Creates a read-only stream over the contents of `blob`, without copying.  The stream
+    /// keeps `blob` alive.
+    pub fn create_stream_from_blob_read_only(&self, blob: &DxcBlob) -> Result<DxcStream> {
+        let mut stream = None;
+
+        unsafe {
+            self.inner
+                .create_stream_from_blob_read_only(&blob.inner, &mut stream)
+        }
+        .result()?;
+        Ok(DxcStream::new(
+            stream.ok_or(HassleError::NullOutput("IStream"))?,
+        ))
     }
 
     /// Convert or return matching encoded text blob as UTF-8.
@@ -537,67 +1198,315 @@ impl DxcLibrary {
 
         unsafe { self.inner.get_blob_as_utf8(&blob.inner, &mut blob_utf8) }.result()?;
 
-        let blob_utf8 = DxcBlobEncoding::new(blob_utf8.unwrap());
-        assert_eq!(blob_utf8.encoding()?, DxcEncoding::Utf8);
+        let blob_utf8 =
+            DxcBlobEncoding::new(blob_utf8.ok_or(HassleError::NullOutput("UTF-8 blob"))?);
+        match blob_utf8.encoding()? {
+            DxcEncoding::Utf8 => Ok(blob_utf8),
+            encoding => Err(HassleError::NotUtf8(encoding)),
+        }
+    }
+
+    /// Convert or return matching encoded text blob as DXC's "wide" encoding, [`CP_WIDE`].  This
+    /// is UTF-16 on Windows, but UTF-32 elsewhere as it follows the size of `wchar_t`.  Use
+    /// [`DxcBlobEncoding::decode()`] to read it.
+    pub fn get_blob_as_utf16(&self, blob: &DxcBlob) -> Result<DxcBlobEncoding> {
+        let mut blob_wide = None;
+
+        unsafe { self.inner.get_blob_as_utf16(&blob.inner, &mut blob_wide) }.result()?;
+
+        let blob_wide =
+            DxcBlobEncoding::new(blob_wide.ok_or(HassleError::NullOutput("wide blob"))?);
+        match blob_wide.encoding()? {
+            encoding if encoding.code_page() == Some(CP_WIDE) => Ok(blob_wide),
+            encoding => Err(HassleError::NotWide(encoding)),
+        }
+    }
+}
+
+/// An `IStream` implemented by DXC, read through [`std::io::Read`] and [`std::io::Seek`].
+pub struct DxcStream {
+    inner: IStream,
+}
+
+unsafe impl Send for DxcStream {}
+
+impl DxcStream {
+    fn new(inner: IStream) -> Self {
+        Self { inner }
+    }
+}
+
+fn io_error(hr: HRESULT) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, HassleError::Win32Error(hr))
+}
+
+impl std::io::Read for DxcStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+        let mut read = 0;
+        // S_FALSE signals that fewer bytes than requested were read
+        let hr = unsafe { self.inner.read(buf.as_mut_ptr().cast(), len, &mut read) };
+        if hr.is_err() {
+            return Err(io_error(hr));
+        }
+        Ok(read as usize)
+    }
+}
+
+impl std::io::Seek for DxcStream {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let (offset, origin) = match pos {
+            std::io::SeekFrom::Start(offset) => (
+                i64::try_from(offset).map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "Seek offset out of range",
+                    )
+                })?,
+                STREAM_SEEK_SET,
+            ),
+            std::io::SeekFrom::Current(offset) => (offset, STREAM_SEEK_CUR),
+            std::io::SeekFrom::End(offset) => (offset, STREAM_SEEK_END),
+        };
+        let mut position = 0;
+        let hr = unsafe { self.inner.seek(offset, origin, &mut position) };
+        if hr.is_err() {
+            return Err(io_error(hr));
+        }
+        Ok(position)
+    }
+}
+
+/// Size of the header in front of every block handed out by [`DxcMallocWrapper`], which stores
+/// the requested size.  This is also the alignment of every block, matching
+/// `MEMORY_ALLOCATION_ALIGNMENT` on 64-bit Windows.
+const MALLOC_HEADER_SIZE: usize = 16;
+
+class! {
+    #[no_class_factory]
+    class DxcMallocWrapper: IMalloc {
+        allocator: Arc<dyn GlobalAlloc + Send + Sync>,
+    }
+
+    impl IMalloc for DxcMallocWrapper {
+        fn alloc(&self, cb: usize) -> *mut c_void {
+            self.alloc_block(cb)
+        }
+
+        fn realloc(&self, pv: *mut c_void, cb: usize) -> *mut c_void {
+            if pv.is_null() {
+                return self.alloc_block(cb);
+            }
+            if cb == 0 {
+                self.free_block(pv);
+                return std::ptr::null_mut();
+            }
+
+            let (base, layout) = unsafe { Self::block(pv) };
+            let new_size = match Self::layout(cb) {
+                Some(new_layout) => new_layout.size(),
+                None => return std::ptr::null_mut(),
+            };
+            // Never unwind into DXC
+            let new_base = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+                self.allocator.realloc(base, layout, new_size)
+            }))
+            .unwrap_or(std::ptr::null_mut());
+            unsafe { Self::init_block(new_base, cb) }
+        }
+
+        fn free(&self, pv: *mut c_void) {
+            self.free_block(pv)
+        }
+
+        fn get_size(&self, pv: *mut c_void) -> usize {
+            if pv.is_null() {
+                return usize::MAX;
+            }
+            unsafe { pv.cast::<u8>().sub(MALLOC_HEADER_SIZE).cast::<usize>().read() }
+        }
+
+        fn did_alloc(&self, _pv: *mut c_void) -> i32 {
+            // Unknown; blocks are not tracked
+            -1
+        }
+
+        fn heap_minimize(&self) {}
+    }
+}
+
+impl DxcMallocWrapper {
+    fn layout(size: usize) -> Option<Layout> {
+        let size = size.checked_add(MALLOC_HEADER_SIZE)?;
+        Layout::from_size_align(size, MALLOC_HEADER_SIZE).ok()
+    }
+
+    /// Writes the size header to a block returned by the allocator, and returns the pointer
+    /// handed to DXC.
+    unsafe fn init_block(base: *mut u8, size: usize) -> *mut c_void {
+        if base.is_null() {
+            return std::ptr::null_mut();
+        }
+        base.cast::<usize>().write(size);
+        base.add(MALLOC_HEADER_SIZE).cast()
+    }
+
+    /// Returns the allocation and layout of a pointer handed to DXC.
+    unsafe fn block(pv: *mut c_void) -> (*mut u8, Layout) {
+        let base = pv.cast::<u8>().sub(MALLOC_HEADER_SIZE);
+        let size = base.cast::<usize>().read();
+        let layout =
+            Layout::from_size_align_unchecked(size + MALLOC_HEADER_SIZE, MALLOC_HEADER_SIZE);
+        (base, layout)
+    }
 
-        Ok(blob_utf8)
+    fn alloc_block(&self, size: usize) -> *mut c_void {
+        let layout = match Self::layout(size) {
+            Some(layout) => layout,
+            None => return std::ptr::null_mut(),
+        };
+        let base = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+            self.allocator.alloc(layout)
+        }))
+        .unwrap_or(std::ptr::null_mut());
+        unsafe { Self::init_block(base, size) }
+    }
+
+    fn free_block(&self, pv: *mut c_void) {
+        if pv.is_null() {
+            return;
+        }
+        let (base, layout) = unsafe { Self::block(pv) };
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+            self.allocator.dealloc(base, layout)
+        }));
     }
 }
 
+/// The [`IMalloc`] handed to `DxcCreateInstance2`.
+#[derive(Debug)]
+struct DxcMalloc(IMalloc);
+
+// `DxcMallocWrapper` is reference counted atomically and only calls into a `Send + Sync`
+// allocator, so DXC may use it from any thread.
+unsafe impl Send for DxcMalloc {}
+unsafe impl Sync for DxcMalloc {}
+
 #[derive(Debug)]
 pub struct Dxc {
     dxc_lib: Library,
+    lib_path: PathBuf,
+    malloc: Option<DxcMalloc>,
 }
 
 impl Dxc {
-    /// `lib_path` is an optional path to the library.  Otherwise
-    /// [`libloading::library_filename("dxcompiler")`] is used.
+    /// `lib_path` is an optional path to the library or the directory containing it.  Otherwise
+    /// the library is searched for as described by [`LibrarySearch::candidates()`], returning
+    /// [`HassleError::LibraryNotFound`] with every path that was tried when it cannot be found.
     pub fn new(lib_path: Option<PathBuf>) -> Result<Self> {
-        let lib_path = lib_path.unwrap_or_else(|| PathBuf::from(library_filename("dxcompiler")));
+        let (dxc_lib, lib_path) = LibrarySearch::dxcompiler().load(lib_path)?;
+        Ok(Self {
+            dxc_lib,
+            lib_path,
+            malloc: None,
+        })
+    }
 
-        let dxc_lib =
-            unsafe { Library::new(&lib_path) }.map_err(|e| HassleError::LoadLibraryError {
-                filename: lib_path,
-                inner: e,
-            })?;
+    /// Routes every allocation of objects created afterwards through `allocator`, by creating
+    /// them with `DxcCreateInstance2`.  Objects created before this call keep using the default
+    /// allocator.
+    ///
+    /// ```no_run
+    /// use hassle_rs::Dxc;
+    /// use std::alloc::{GlobalAlloc, Layout, System};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// #[derive(Default)]
+    /// struct Tracking {
+    ///     current: AtomicUsize,
+    ///     peak: AtomicUsize,
+    /// }
+    ///
+    /// unsafe impl GlobalAlloc for Tracking {
+    ///     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ///         let current = self.current.fetch_add(layout.size(), Ordering::Relaxed);
+    ///         self.peak.fetch_max(current + layout.size(), Ordering::Relaxed);
+    ///         System.alloc(layout)
+    ///     }
+    ///
+    ///     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    ///         self.current.fetch_sub(layout.size(), Ordering::Relaxed);
+    ///         System.dealloc(ptr, layout)
+    ///     }
+    /// }
+    ///
+    /// let tracking = Arc::new(Tracking::default());
+    /// let dxc = Dxc::new(None)?.with_allocator(tracking.clone())?;
+    /// let compiler = dxc.create_compiler()?;
+    /// // ...
+    /// println!("Peak: {} bytes", tracking.peak.load(Ordering::Relaxed));
+    /// # Ok::<(), hassle_rs::HassleError>(())
+    /// ```
+    pub fn with_allocator(mut self, allocator: Arc<dyn GlobalAlloc + Send + Sync>) -> Result<Self> {
+        let wrapper = DxcMallocWrapper::allocate(allocator);
+        let malloc = wrapper
+            .query_interface::<IMalloc>()
+            .ok_or(HassleError::NullOutput("IMalloc"))?;
+        self.malloc = Some(DxcMalloc(malloc));
+        Ok(self)
+    }
 
-        Ok(Self { dxc_lib })
+    /// The path the library was loaded from.  This is a bare file name when it was found through
+    /// the platform loader's search path.
+    pub fn path(&self) -> &Path {
+        &self.lib_path
     }
 
-    pub(crate) fn get_dxc_create_instance<T>(
-        &self,
-    ) -> Result<Symbol<'_, DxcCreateInstanceProc<T>>> {
+    fn get_dxc_create_instance<T>(&self) -> Result<Symbol<'_, DxcCreateInstanceProc<T>>> {
         Ok(unsafe { self.dxc_lib.get(b"DxcCreateInstance\0")? })
     }
 
-    pub fn create_compiler(&self) -> Result<DxcCompiler> {
-        let mut compiler = None;
+    fn get_dxc_create_instance2<T>(&self) -> Result<Symbol<'_, DxcCreateInstanceProc2<T>>> {
+        Ok(unsafe { self.dxc_lib.get(b"DxcCreateInstance2\0")? })
+    }
 
-        self.get_dxc_create_instance()?(&CLSID_DxcCompiler, &IDxcCompiler2::IID, &mut compiler)
-            .result()?;
+    /// Creates an instance of `clsid` with the allocator set by [`Self::with_allocator()`], if
+    /// any.
+    pub(crate) fn create_instance<T: Interface>(&self, clsid: &IID) -> Result<T> {
+        let mut instance = None;
+        match &self.malloc {
+            Some(malloc) => self.get_dxc_create_instance2()?(
+                malloc.0.as_raw().as_ptr().cast(),
+                clsid,
+                &T::IID,
+                &mut instance,
+            ),
+            None => self.get_dxc_create_instance()?(clsid, &T::IID, &mut instance),
+        }
+        .result()?;
+        instance.ok_or(HassleError::NullOutput(std::any::type_name::<T>()))
+    }
+
+    pub fn create_compiler(&self) -> Result<DxcCompiler> {
         Ok(DxcCompiler::new(
-            compiler.unwrap(),
-            self.create_library().unwrap(),
+            self.create_instance(&CLSID_DxcCompiler)?,
+            self.create_library()?,
         ))
     }
 
     pub fn create_library(&self) -> Result<DxcLibrary> {
-        let mut library = None;
-        self.get_dxc_create_instance()?(&CLSID_DxcLibrary, &IDxcLibrary::IID, &mut library)
-            .result()?;
-        Ok(DxcLibrary::new(library.unwrap()))
+        Ok(DxcLibrary::new(self.create_instance(&CLSID_DxcLibrary)?))
     }
 
     pub fn create_reflector(&self) -> Result<DxcReflector> {
-        let mut reflector = None;
+        Ok(DxcReflector::new(
+            self.create_instance(&CLSID_DxcContainerReflection)?,
+        ))
+    }
 
-        self.get_dxc_create_instance()?(
-            &CLSID_DxcContainerReflection,
-            &IDxcContainerReflection::IID,
-            &mut reflector,
-        )
-        .result()?;
-        Ok(DxcReflector::new(reflector.unwrap()))
+    pub fn create_pdb_utils(&self) -> Result<DxcPdbUtils> {
+        Ok(DxcPdbUtils::new(self.create_instance(&CLSID_DxcPdbUtils)?))
     }
 }
 
@@ -624,7 +1533,13 @@ impl DxcValidator {
         unsafe { version.get_version(&mut major, &mut minor) }.result_with_success((major, minor))
     }
 
+    /// Validates and signs `blob` in place, returning [`HassleError::ReadOnlyBlob`] for blobs
+    /// that cannot be modified.
     pub fn validate(&self, blob: &DxcBlob) -> Result<DxcOperationResult> {
+        if blob.is_read_only() {
+            return Err(HassleError::ReadOnlyBlob);
+        }
+
         let mut result = None;
         unsafe {
             self.inner
@@ -632,7 +1547,7 @@ impl DxcValidator {
         }
         .result()?;
 
-        let result = result.expect("Non-null IDxcOperationResult");
+        let result = result.ok_or(HassleError::NullOutput("IDxcOperationResult"))?;
         Ok(DxcOperationResult::new(result))
     }
 }
@@ -653,6 +1568,137 @@ impl Reflection {
         };
         [size_x, size_y, size_z]
     }
+
+    fn desc(&self) -> Result<D3D12ShaderDesc> {
+        let mut desc = unsafe { std::mem::zeroed::<D3D12ShaderDesc>() };
+        unsafe {
+            self.inner
+                .get_desc((&mut desc as *mut D3D12ShaderDesc).cast())
+        }
+        .result_with_success(desc)
+    }
+
+    /// Returns the raw `D3D_SHADER_REQUIRES_*` flags.
+    pub fn requires_flags(&self) -> u64 {
+        unsafe { self.inner.get_requires_flags() }
+    }
+
+    /// Returns every resource that is bound to the shader.
+    pub fn bound_resources(&self) -> Result<Vec<ShaderInputBinding>> {
+        (0..self.desc()?.bound_resources)
+            .map(|index| {
+                let mut desc = unsafe { std::mem::zeroed::<D3D12ShaderInputBindDesc>() };
+                unsafe {
+                    self.inner.get_resource_binding_desc(
+                        index,
+                        (&mut desc as *mut D3D12ShaderInputBindDesc).cast(),
+                    )
+                }
+                .result()?;
+                Ok(ShaderInputBinding {
+                    name: from_lpstr(desc.name)?,
+                    input_type: desc.input_type,
+                    bind_point: desc.bind_point,
+                    bind_count: desc.bind_count,
+                    flags: desc.flags,
+                    return_type: desc.return_type,
+                    dimension: desc.dimension,
+                    num_samples: desc.num_samples,
+                    space: desc.space,
+                    id: desc.id,
+                })
+            })
+            .collect()
+    }
+
+    pub fn input_parameters(&self) -> Result<Vec<SignatureParameter>> {
+        (0..self.desc()?.input_parameters)
+            .map(|index| {
+                let mut desc = unsafe { std::mem::zeroed::<D3D12SignatureParameterDesc>() };
+                unsafe {
+                    self.inner.get_input_parameter_desc(
+                        index,
+                        (&mut desc as *mut D3D12SignatureParameterDesc).cast(),
+                    )
+                }
+                .result()?;
+                SignatureParameter::from_desc(&desc)
+            })
+            .collect()
+    }
+
+    pub fn output_parameters(&self) -> Result<Vec<SignatureParameter>> {
+        (0..self.desc()?.output_parameters)
+            .map(|index| {
+                let mut desc = unsafe { std::mem::zeroed::<D3D12SignatureParameterDesc>() };
+                unsafe {
+                    self.inner.get_output_parameter_desc(
+                        index,
+                        (&mut desc as *mut D3D12SignatureParameterDesc).cast(),
+                    )
+                }
+                .result()?;
+                SignatureParameter::from_desc(&desc)
+            })
+            .collect()
+    }
+}
+
+/// A resource bound to a shader, see [`Reflection::bound_resources()`].
+///
+/// The enumerations are kept as their raw `D3D_*` values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderInputBinding {
+    pub name: String,
+    /// `D3D_SHADER_INPUT_TYPE`
+    pub input_type: u32,
+    pub bind_point: u32,
+    pub bind_count: u32,
+    /// `D3D_SHADER_INPUT_FLAGS`
+    pub flags: u32,
+    /// `D3D_RESOURCE_RETURN_TYPE`
+    pub return_type: u32,
+    /// `D3D_SRV_DIMENSION`
+    pub dimension: u32,
+    pub num_samples: u32,
+    pub space: u32,
+    pub id: u32,
+}
+
+/// An input or output parameter of a shader, see [`Reflection::input_parameters()`] and
+/// [`Reflection::output_parameters()`].
+///
+/// The enumerations are kept as their raw `D3D_*` values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureParameter {
+    pub semantic_name: String,
+    pub semantic_index: u32,
+    pub register: u32,
+    /// `D3D_NAME`
+    pub system_value_type: u32,
+    /// `D3D_REGISTER_COMPONENT_TYPE`
+    pub component_type: u32,
+    pub mask: u8,
+    pub read_write_mask: u8,
+    pub stream: u32,
+    /// `D3D_MIN_PRECISION`
+    pub min_precision: u32,
+}
+
+impl SignatureParameter {
+    fn from_desc(desc: &D3D12SignatureParameterDesc) -> Result<Self> {
+        Ok(Self {
+            semantic_name: from_lpstr(desc.semantic_name)?,
+            semantic_index: desc.semantic_index,
+            register: desc.register,
+            system_value_type: desc.system_value_type,
+            component_type: desc.component_type,
+            mask: desc.mask,
+            read_write_mask: desc.read_write_mask,
+            stream: desc.stream,
+            min_precision: desc.min_precision,
+        })
+    }
 }
 
 pub struct DxcReflector {
@@ -688,29 +1734,192 @@ impl DxcReflector {
         }
 
         Ok(Reflection::new(
-            reflection.unwrap().query_interface().unwrap(),
+            reflection
+                .ok_or(HassleError::NullOutput("part reflection"))?
+                .query_interface()
+                .ok_or(HassleError::NullOutput("ID3D12ShaderReflection"))?,
+        ))
+    }
+}
+
+/// Source file embedded in a shader PDB, see [`DxcPdbUtils::get_sources()`].
+#[derive(Debug)]
+pub struct DxcPdbSource {
+    pub name: String,
+    pub content: DxcBlobEncoding,
+}
+
+/// Inspects the compilation inputs recorded in a shader PDB (as returned by
+/// [`DxcCompiler::compile_with_debug()`]) or in a DXIL container carrying debug information.
+///
+/// Call [`Self::load()`] before any of the getters.
+pub struct DxcPdbUtils {
+    inner: IDxcPdbUtils,
+}
+
+impl DxcPdbUtils {
+    fn new(inner: IDxcPdbUtils) -> Self {
+        Self { inner }
+    }
+
+    pub fn load(&self, blob: &DxcBlob) -> Result<()> {
+        unsafe { self.inner.load(&blob.inner) }.result()
+    }
+
+    pub fn get_source_count(&self) -> Result<u32> {
+        let mut count = 0;
+        unsafe { self.inner.get_source_count(&mut count) }.result_with_success(count)
+    }
+
+    pub fn get_source(&self, index: u32) -> Result<DxcBlobEncoding> {
+        let mut blob = None;
+        unsafe { self.inner.get_source(index, &mut blob) }.result()?;
+        Ok(DxcBlobEncoding::new(
+            blob.ok_or(HassleError::NullOutput("source blob"))?,
+        ))
+    }
+
+    pub fn get_source_name(&self, index: u32) -> Result<String> {
+        let mut name: BSTR = std::ptr::null_mut();
+        unsafe { self.inner.get_source_name(index, &mut name) }.result()?;
+        from_bstr(name)
+    }
+
+    /// Returns every source file embedded in the PDB, including the main file.
+    pub fn get_sources(&self) -> Result<Vec<DxcPdbSource>> {
+        (0..self.get_source_count()?)
+            .map(|i| {
+                Ok(DxcPdbSource {
+                    name: self.get_source_name(i)?,
+                    content: self.get_source(i)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the compiler flags, i.e. the arguments that do not take a value.
+    pub fn get_flags(&self) -> Result<Vec<String>> {
+        let mut count = 0;
+        unsafe { self.inner.get_flag_count(&mut count) }.result()?;
+        (0..count)
+            .map(|i| {
+                let mut flag: BSTR = std::ptr::null_mut();
+                unsafe { self.inner.get_flag(i, &mut flag) }.result()?;
+                from_bstr(flag)
+            })
+            .collect()
+    }
+
+    /// Returns the full argument list that was passed to the compiler.
+    pub fn get_args(&self) -> Result<Vec<String>> {
+        let mut count = 0;
+        unsafe { self.inner.get_arg_count(&mut count) }.result()?;
+        (0..count)
+            .map(|i| {
+                let mut arg: BSTR = std::ptr::null_mut();
+                unsafe { self.inner.get_arg(i, &mut arg) }.result()?;
+                from_bstr(arg)
+            })
+            .collect()
+    }
+
+    /// Returns the compiler arguments as `(name, value)` pairs, where either side may be empty.
+    pub fn get_arg_pairs(&self) -> Result<Vec<(String, String)>> {
+        let mut count = 0;
+        unsafe { self.inner.get_arg_pair_count(&mut count) }.result()?;
+        (0..count)
+            .map(|i| {
+                let mut name: BSTR = std::ptr::null_mut();
+                let mut value: BSTR = std::ptr::null_mut();
+                unsafe { self.inner.get_arg_pair(i, &mut name, &mut value) }.result()?;
+                let (name, value) = (from_bstr(name), from_bstr(value));
+                Ok((name?, value?))
+            })
+            .collect()
+    }
+
+    /// Returns the defines in `NAME=VALUE` (or just `NAME`) form.
+    pub fn get_defines(&self) -> Result<Vec<String>> {
+        let mut count = 0;
+        unsafe { self.inner.get_define_count(&mut count) }.result()?;
+        (0..count)
+            .map(|i| {
+                let mut define: BSTR = std::ptr::null_mut();
+                unsafe { self.inner.get_define(i, &mut define) }.result()?;
+                from_bstr(define)
+            })
+            .collect()
+    }
+
+    pub fn get_target_profile(&self) -> Result<String> {
+        let mut profile: BSTR = std::ptr::null_mut();
+        unsafe { self.inner.get_target_profile(&mut profile) }.result()?;
+        from_bstr(profile)
+    }
+
+    pub fn get_entry_point(&self) -> Result<String> {
+        let mut entry_point: BSTR = std::ptr::null_mut();
+        unsafe { self.inner.get_entry_point(&mut entry_point) }.result()?;
+        from_bstr(entry_point)
+    }
+
+    pub fn get_main_file_name(&self) -> Result<String> {
+        let mut name: BSTR = std::ptr::null_mut();
+        unsafe { self.inner.get_main_file_name(&mut name) }.result()?;
+        from_bstr(name)
+    }
+
+    pub fn get_hash(&self) -> Result<DxcBlob> {
+        let mut hash = None;
+        unsafe { self.inner.get_hash(&mut hash) }.result()?;
+        Ok(DxcBlob::new(
+            hash.ok_or(HassleError::NullOutput("hash blob"))?,
         ))
     }
+
+    /// Returns the PDB name, as referenced by the shader's debug name part.
+    pub fn get_name(&self) -> Result<String> {
+        let mut name: BSTR = std::ptr::null_mut();
+        unsafe { self.inner.get_name(&mut name) }.result()?;
+        from_bstr(name)
+    }
+
+    pub fn is_full_pdb(&self) -> bool {
+        unsafe { self.inner.is_full_pdb() != 0 }
+    }
 }
 
 #[derive(Debug)]
 pub struct Dxil {
     dxil_lib: Library,
+    lib_path: PathBuf,
 }
 
 impl Dxil {
-    /// `lib_path` is an optional path to the library.  Otherwise
-    /// [`libloading::library_filename("dxil")`] is used.
+    /// `lib_path` is an optional path to the library or the directory containing it.  Otherwise
+    /// the library is searched for as described by [`LibrarySearch::candidates()`], returning
+    /// [`HassleError::LibraryNotFound`] with every path that was tried when it cannot be found.
     pub fn new(lib_path: Option<PathBuf>) -> Result<Self> {
-        let lib_path = lib_path.unwrap_or_else(|| PathBuf::from(library_filename("dxil")));
+        let (dxil_lib, lib_path) = LibrarySearch::dxil().load(lib_path)?;
+        Ok(Self { dxil_lib, lib_path })
+    }
 
-        let dxil_lib =
-            unsafe { Library::new(&lib_path) }.map_err(|e| HassleError::LoadLibraryError {
-                filename: lib_path.to_owned(),
-                inner: e,
-            })?;
+    /// Loads the library from the directory `dxc` was loaded from, where DXC releases ship it,
+    /// and otherwise searches for it like [`Self::new()`].
+    pub fn new_next_to(dxc: &Dxc) -> Result<Self> {
+        let beside_dxc = dxc
+            .path()
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(|dir| dir.join(LibrarySearch::dxil().file_name()))
+            .filter(|path| path.is_file());
+        Self::new(beside_dxc)
+    }
 
-        Ok(Self { dxil_lib })
+    /// The path the library was loaded from.  This is a bare file name when it was found through
+    /// the platform loader's search path.
+    pub fn path(&self) -> &Path {
+        &self.lib_path
     }
 
     fn get_dxc_create_instance<T>(&self) -> Result<Symbol<'_, DxcCreateInstanceProc<T>>> {
@@ -721,6 +1930,189 @@ impl Dxil {
         let mut validator = None;
         self.get_dxc_create_instance()?(&CLSID_DxcValidator, &IDxcValidator::IID, &mut validator)
             .result()?;
-        Ok(DxcValidator::new(validator.unwrap()))
+        Ok(DxcValidator::new(
+            validator.ok_or(HassleError::NullOutput("IDxcValidator"))?,
+        ))
+    }
+}
+
+#[cfg(test)]
+impl Dxc {
+    /// Wraps the current process instead of DXC, for tests of code that holds a [`Dxc`] but
+    /// never creates objects with it.
+    pub(crate) fn without_library(lib_path: impl Into<PathBuf>) -> Self {
+        #[cfg(unix)]
+        let dxc_lib = libloading::os::unix::Library::this().into();
+        #[cfg(windows)]
+        let dxc_lib = libloading::os::windows::Library::this().unwrap().into();
+        Self {
+            dxc_lib,
+            lib_path: lib_path.into(),
+            malloc: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::System;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counting {
+        allocated: AtomicUsize,
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.allocated.fetch_add(layout.size(), Ordering::Relaxed);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.allocated.fetch_sub(layout.size(), Ordering::Relaxed);
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    fn malloc(allocator: Arc<Counting>) -> IMalloc {
+        DxcMallocWrapper::allocate(allocator)
+            .query_interface::<IMalloc>()
+            .unwrap()
+    }
+
+    #[test]
+    fn owned_blob_from_array() {
+        let blob = DxcBlob::from_owned([7u8; 64]);
+        assert_eq!(blob.as_slice(), &[7u8; 64][..]);
+        assert!(blob.is_read_only());
+
+        let blob = DxcBlobEncoding::from_owned([1u8, 2, 3], Some(CP_UTF8));
+        assert_eq!(DxcBlob::from(blob).as_slice(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn owned_blob_mutability() {
+        let shared: Arc<[u8]> = vec![1u8, 2, 3].into();
+        let mut blob = DxcBlob::from_owned(shared.clone());
+        assert!(matches!(
+            blob.try_as_mut_slice(),
+            Err(HassleError::ReadOnlyBlob)
+        ));
+        // Copied before it is modified, leaving the shared buffer untouched
+        blob.as_mut()[0] = 4;
+        assert!(!blob.is_read_only());
+        assert_eq!(blob.as_slice(), &[4, 2, 3]);
+        assert_eq!(&*shared, &[1, 2, 3]);
+
+        let mut blob = DxcBlob::from_owned_mut([1u8, 2, 3]);
+        assert!(!blob.is_read_only());
+        blob.try_as_mut_slice().unwrap()[1] = 5;
+        blob.as_mut_slice()[2] = 6;
+        assert_eq!(blob.as_slice(), &[1, 5, 6]);
+    }
+
+    fn decode(data: &'static [u8], code_page: Option<u32>) -> Result<String> {
+        Ok(DxcBlobEncoding::from_owned(data, code_page)
+            .decode()?
+            .into_owned())
+    }
+
+    #[test]
+    fn encoding_code_pages_round_trip() {
+        for code_page in [CP_UTF8, CP_UTF16, CP_UTF16BE, CP_UTF32, CP_UTF32BE, CP_ACP] {
+            assert_eq!(
+                DxcEncoding::from_code_page(code_page).code_page(),
+                Some(code_page)
+            );
+        }
+        assert_eq!(
+            DxcEncoding::from_code_page(CP_UTF16BE),
+            DxcEncoding::Utf16Be
+        );
+        assert_eq!(
+            DxcEncoding::from_code_page(CP_UTF32BE),
+            DxcEncoding::Utf32Be
+        );
+    }
+
+    #[test]
+    fn decode_detects_bom_without_declared_encoding() {
+        assert_eq!(decode(b"\xEF\xBB\xBFfloat4\0", None).unwrap(), "float4");
+        assert_eq!(decode(b"\xFF\xFEf\0", None).unwrap(), "f");
+        assert_eq!(decode(b"\xFE\xFF\0f", None).unwrap(), "f");
+        assert_eq!(decode(b"\xFF\xFE\0\0f\0\0\0", None).unwrap(), "f");
+        assert_eq!(decode(b"\0\0\xFE\xFF\0\0\0f", None).unwrap(), "f");
+        assert_eq!(decode(b"float4", None).unwrap(), "float4");
+    }
+
+    #[test]
+    fn decode_only_trusts_bom_of_declared_width() {
+        // A UTF-16 BOM followed by U+0000, not a UTF-32 BOM
+        assert_eq!(decode(b"\xFF\xFE\0\0A\0", Some(CP_UTF16)).unwrap(), "\0A");
+        // The BOM overrides the declared endianness
+        assert_eq!(decode(b"\xFE\xFF\0A", Some(CP_UTF16)).unwrap(), "A");
+        assert_eq!(decode(b"\0A", Some(CP_UTF16BE)).unwrap(), "A");
+        assert_eq!(decode(b"\0\0\0A\0\0\0\0", Some(CP_UTF32BE)).unwrap(), "A");
+        // A UTF-8 BOM is kept in UTF-16 text
+        assert_eq!(
+            decode(b"\xEF\xBB\xBF\0", Some(CP_UTF16)).unwrap(),
+            "\u{BBEF}\u{BF}"
+        );
+        assert!(matches!(
+            decode(b"float4", Some(CP_ACP)),
+            Err(HassleError::UnsupportedCodePage(CP_ACP))
+        ));
+    }
+
+    #[test]
+    fn malloc_layout_includes_header() {
+        let layout = DxcMallocWrapper::layout(100).unwrap();
+        assert_eq!(layout.size(), 100 + MALLOC_HEADER_SIZE);
+        assert_eq!(layout.align(), MALLOC_HEADER_SIZE);
+        assert!(DxcMallocWrapper::layout(usize::MAX).is_none());
+    }
+
+    #[test]
+    fn malloc_blocks_record_their_size() {
+        let counting = Arc::new(Counting::default());
+        let malloc = malloc(counting.clone());
+        unsafe {
+            let pv = malloc.alloc(100);
+            assert!(!pv.is_null());
+            assert_eq!(pv as usize % MALLOC_HEADER_SIZE, 0);
+            assert_eq!(malloc.get_size(pv), 100);
+            assert_eq!(
+                counting.allocated.load(Ordering::Relaxed),
+                100 + MALLOC_HEADER_SIZE
+            );
+            std::ptr::write_bytes(pv.cast::<u8>(), 0xab, 100);
+
+            let pv = malloc.realloc(pv, 300);
+            assert!(!pv.is_null());
+            assert_eq!(malloc.get_size(pv), 300);
+            assert_eq!(*pv.cast::<u8>().add(99), 0xab);
+
+            malloc.free(pv);
+            assert_eq!(counting.allocated.load(Ordering::Relaxed), 0);
+        }
+    }
+
+    #[test]
+    fn malloc_handles_null_and_zero() {
+        let counting = Arc::new(Counting::default());
+        let malloc = malloc(counting.clone());
+        unsafe {
+            assert_eq!(malloc.get_size(std::ptr::null_mut()), usize::MAX);
+            malloc.free(std::ptr::null_mut());
+
+            let pv = malloc.realloc(std::ptr::null_mut(), 8);
+            assert_eq!(malloc.get_size(pv), 8);
+            assert!(malloc.realloc(pv, 0).is_null());
+            assert_eq!(counting.allocated.load(Ordering::Relaxed), 0);
+
+            assert!(malloc.alloc(usize::MAX).is_null());
+        }
     }
 }